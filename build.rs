@@ -0,0 +1,98 @@
+//! Snapshots the `content/` directory into `$OUT_DIR/content.rs`, which `FsTree::default`
+//! replays to build the filesystem visitors start with.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const CONTENT_DIR: &str = "content";
+
+/// Placeholder files that only exist so git keeps otherwise empty directories.
+const IGNORED_NAMES: &[&str] = &[".gitkeep"];
+
+fn main() {
+    println!("cargo:rerun-if-changed={CONTENT_DIR}");
+
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join(CONTENT_DIR);
+    let mut generated = String::from("pub static CONTENT: &[ContentEntry] = &[\n");
+    if root.is_dir() {
+        walk(&root, &root, &mut generated);
+    }
+    generated.push_str("];\n");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("content.rs");
+    fs::write(out_path, generated).unwrap();
+}
+
+/// Appends an entry for everything below `dir`, parents before their children.
+fn walk(root: &Path, dir: &Path, generated: &mut String) {
+    let mut children = fs::read_dir(dir)
+        .unwrap_or_else(|error| panic!("failed to read {}: {error}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    children.sort();
+
+    for path in children {
+        let name = path.file_name().unwrap().to_string_lossy();
+        if IGNORED_NAMES.contains(&name.as_ref()) {
+            continue;
+        }
+
+        let relative = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_str()
+            .unwrap_or_else(|| panic!("content path is not UTF-8: {}", path.display()));
+        let metadata = fs::symlink_metadata(&path).unwrap();
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(&path).unwrap();
+            writeln!(
+                generated,
+                "    ContentEntry::Symlink {{ path: {relative:?}, target: {:?} }},",
+                target.to_str().unwrap()
+            )
+            .unwrap();
+        } else if metadata.is_dir() {
+            writeln!(
+                generated,
+                "    ContentEntry::Directory {{ path: {relative:?}, mode: {:#o} }},",
+                mode(&metadata, 0o755)
+            )
+            .unwrap();
+            walk(root, &path, generated);
+        } else if fs::read_to_string(&path).is_ok() {
+            writeln!(
+                generated,
+                "    ContentEntry::File {{ path: {relative:?}, contents: include_str!({:?}), mode: {:#o} }},",
+                path.to_str().unwrap(),
+                mode(&metadata, 0o644)
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                generated,
+                "    ContentEntry::BinaryFile {{ path: {relative:?}, contents: include_bytes!({:?}), mode: {:#o} }},",
+                path.to_str().unwrap(),
+                mode(&metadata, 0o644)
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Permission bits of a content file, so executables in `content/` stay executable.
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata, _default: u32) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata, default: u32) -> u32 {
+    default
+}
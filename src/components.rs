@@ -1,9 +1,6 @@
-use yew::{AttrValue, Html, Properties, classes, function_component, html, props};
+use yew::{AttrValue, Html, Properties, classes, function_component, html};
 
-use crate::{
-    StatusCode,
-    fs::{FsIndex, FsTree},
-};
+use crate::StatusCode;
 
 #[derive(Properties, PartialEq)]
 pub struct PromptProps {
@@ -0,0 +1,68 @@
+use unix_path::Path;
+
+use crate::fs::{Contents, FsError, FsTree};
+
+/// A node snapshotted from the repository's `content/` directory by the build script. Paths are
+/// relative to the filesystem root, and parents always precede their children.
+pub enum ContentEntry {
+    Directory {
+        path: &'static str,
+        mode: u16,
+    },
+    File {
+        path: &'static str,
+        contents: &'static str,
+        mode: u16,
+    },
+    /// A file that is not valid UTF-8, such as an image.
+    BinaryFile {
+        path: &'static str,
+        contents: &'static [u8],
+        mode: u16,
+    },
+    Symlink {
+        path: &'static str,
+        target: &'static str,
+    },
+}
+
+include!(concat!(env!("OUT_DIR"), "/content.rs"));
+
+/// Recreates the embedded `content/` directory inside `fs_tree`.
+pub fn populate(fs_tree: &mut FsTree) -> Result<(), FsError> {
+    for entry in CONTENT {
+        let (ContentEntry::Directory { path, .. }
+        | ContentEntry::File { path, .. }
+        | ContentEntry::BinaryFile { path, .. }
+        | ContentEntry::Symlink { path, .. }) = entry;
+
+        let path = Path::new("/").join(path);
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(FsError::InvalidName)?;
+        let parent = fs_tree.lookup_path(path.parent().ok_or(FsError::InvalidName)?)?;
+
+        match entry {
+            ContentEntry::Directory { mode, .. } => {
+                let index = fs_tree.create_directory(name, parent)?;
+                fs_tree.set_mode(index, *mode)?;
+            }
+            ContentEntry::File { contents, mode, .. } => {
+                let index = fs_tree.create_file(name, parent)?;
+                fs_tree.write(index, *contents)?;
+                fs_tree.set_mode(index, *mode)?;
+            }
+            ContentEntry::BinaryFile { contents, mode, .. } => {
+                let index = fs_tree.create_file(name, parent)?;
+                fs_tree.write(index, Contents::Binary((*contents).into()))?;
+                fs_tree.set_mode(index, *mode)?;
+            }
+            ContentEntry::Symlink { target, .. } => {
+                fs_tree.create_symlink(name, parent, target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
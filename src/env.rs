@@ -0,0 +1,17 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::HOME;
+
+thread_local! {
+    static VARIABLES: RefCell<HashMap<String, String>> =
+        RefCell::new(HashMap::from([("HOME".into(), HOME.into())]));
+}
+
+/// Reads a shell variable, mirroring `std::env::var` for the single-threaded wasm shell.
+pub fn var(name: &str) -> Option<String> {
+    VARIABLES.with_borrow(|variables| variables.get(name).cloned())
+}
+
+pub fn set_var(name: &str, value: &str) {
+    VARIABLES.with_borrow_mut(|variables| variables.insert(name.into(), value.into()));
+}
@@ -1,13 +1,37 @@
-use std::{collections::HashMap, rc::Rc};
+use std::rc::Rc;
 
+use im_rc::{HashMap, Vector};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use unix_path::{Path, PathBuf};
+use unix_path::{Component, Path, PathBuf};
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsValue;
+#[cfg(target_arch = "wasm32")]
+use web_sys::js_sys::Date;
 use web_sys::js_sys::eval;
 use yew::{AttrValue, Html, html};
 
-use crate::StatusCode;
+use self::{device::Device, quota::Totals, search::SearchIndex};
+use crate::{
+    StatusCode, content,
+    proc::{self, Generator},
+};
+
+mod check;
+mod contents;
+mod device;
+mod overlay;
+mod quota;
+mod search;
+mod snapshot;
+
+pub use check::Violation;
+pub use contents::Contents;
+pub use device::set_clipboard;
+pub use overlay::UpperLayer;
+pub use quota::{Quota, Usage};
+pub use search::tokens;
+pub use snapshot::Snapshot;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 struct Directory {
@@ -32,63 +56,57 @@ impl DirEntry {
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 struct File {
-    contents: Rc<str>,
+    contents: Contents,
 }
 
 impl Directory {
-    pub fn new_root() -> Self {
-        Default::default()
-    }
-
     pub fn new(parent: FsIndex, this: FsIndex) -> Self {
         Self {
-            entries: HashMap::from([("..".into(), parent), (".".into(), this)]),
+            entries: HashMap::from_iter([("..".into(), parent), (".".into(), this)]),
         }
     }
 
+    fn is_empty(&self) -> bool {
+        self.entries
+            .keys()
+            .all(|name| &**name == "." || &**name == "..")
+    }
+
     fn children(&self) -> impl Iterator<Item = DirEntry> {
         self.entries.iter().map(|(k, v)| DirEntry {
             name: Rc::clone(k),
             index: *v,
         })
     }
-
-    pub fn execute(&self) -> (Html, u32) {
-        unimplemented!();
-    }
 }
 
 impl Default for Directory {
     fn default() -> Self {
         Self {
-            entries: HashMap::from([("..".into(), FsIndex(0)), (".".into(), FsIndex(0))]),
+            entries: HashMap::from_iter([
+                ("..".into(), FsIndex::ROOT),
+                (".".into(), FsIndex::ROOT),
+            ]),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
-struct JsProgramResult {
-    output: String,
-    #[serde(rename = "returnCode")]
-    return_code: u32,
-}
-
 impl File {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn contents(&self) -> Rc<str> {
+    pub fn contents(&self) -> Contents {
         self.contents.clone()
     }
 
-    pub fn write(&mut self, contents: &str) {
-        self.contents = contents.into();
+    pub fn write(&mut self, contents: Contents) {
+        self.contents = contents;
     }
 
-    // TODO this should return an option
-    pub fn execute(&self) -> (Html, StatusCode) {
-        match eval(&self.contents()) {
+    pub fn execute(&self) -> Result<(Html, StatusCode), FsError> {
+        let source = self.contents.text()?;
+        Ok(match eval(&source) {
             Ok(output) => output.as_string().map_or(
                 (html!(<>{"invalid program result"}</>), StatusCode(1)),
                 |o| {
@@ -98,131 +116,751 @@ impl File {
                     )
                 },
             ),
-            Err(error) => {
+            Err(_) => {
                 // TODO robustify this?
                 (html!(<>{"program encountered an error"}</>), StatusCode(1))
             }
-        }
+        })
     }
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Symlink {
+    target: Rc<str>,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 enum FsNode {
     Directory(Directory),
     File(File),
+    Symlink(Symlink),
+    /// A read-only file or link whose contents are generated whenever it is read.
+    Generated(Generator),
+    Device(Device),
+}
+
+impl FsNode {
+    fn file_type(&self) -> FileType {
+        match self {
+            FsNode::Directory(_) => FileType::Directory,
+            FsNode::File(_) => FileType::File,
+            FsNode::Symlink(_) => FileType::Symlink,
+            FsNode::Generated(generator) => generator.file_type(),
+            FsNode::Device(_) => FileType::CharDevice,
+        }
+    }
+
+    /// Generated nodes and devices report a size of zero, as they do on Linux.
+    fn size(&self) -> usize {
+        match self {
+            FsNode::Directory(dir) => dir.entries.len(),
+            FsNode::File(file) => file.contents.len(),
+            FsNode::Symlink(link) => link.target.len(),
+            FsNode::Generated(_) | FsNode::Device(_) => 0,
+        }
+    }
+
+    fn default_mode(&self) -> u16 {
+        match self.file_type() {
+            FileType::Directory => 0o755,
+            FileType::File if matches!(self, FsNode::Generated(_)) => 0o444,
+            FileType::File => 0o644,
+            FileType::Symlink => 0o777,
+            FileType::CharDevice => 0o666,
+        }
+    }
+
+    /// The target of a symbolic link, whether stored or generated.
+    fn link_target(&self) -> Option<Rc<str>> {
+        match self {
+            FsNode::Symlink(link) => Some(Rc::clone(&link.target)),
+            FsNode::Generated(generator) if generator.file_type() == FileType::Symlink => {
+                Some(generator.generate())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, as reported by the browser.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> u64 {
+    Date::now() as u64
+}
+
+/// `js_sys::Date` is unavailable off the web, so native builds fall back to the system clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Milliseconds to add to a UTC time at `millis` to get the visitor's local time.
+#[cfg(target_arch = "wasm32")]
+fn utc_offset(millis: u64) -> i64 {
+    let offset_minutes = Date::new(&JsValue::from_f64(millis as f64)).get_timezone_offset();
+    -(offset_minutes * 60_000.0) as i64
+}
+
+/// Native builds don't know the visitor's time zone, so local times are given in UTC.
+#[cfg(not(target_arch = "wasm32"))]
+fn utc_offset(_millis: u64) -> i64 {
+    0
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
-pub struct FsIndex(usize);
+/// A moment broken down into calendar fields, for formatting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct DateTime {
+    pub year: i64,
+    /// From 1 for January.
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+impl DateTime {
+    /// Breaks down `millis` since the Unix epoch in UTC.
+    pub(crate) fn utc(millis: u64) -> Self {
+        const DAY: u64 = 24 * 60 * 60 * 1000;
+        let (days, time) = ((millis / DAY) as i64, (millis % DAY) as u32);
+
+        // Howard Hinnant's `civil_from_days`, over 400-year eras starting on March 1st
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+
+        Self {
+            year: year_of_era + era * 400 + i64::from(month <= 2),
+            month: month as u32,
+            day: (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32,
+            hour: time / 3_600_000,
+            minute: time / 60_000 % 60,
+            second: time / 1000 % 60,
+            millisecond: time % 1000,
+        }
+    }
+
+    /// Breaks down `millis` since the Unix epoch in the visitor's time zone.
+    pub(crate) fn local(millis: u64) -> Self {
+        Self::utc(millis.saturating_add_signed(utc_offset(millis)))
+    }
+}
+
+/// The metadata `stat` reports about a node, apart from anything derived from the node itself.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Attributes {
+    mode: u16,
+    owner: Rc<str>,
+    created: u64,
+    modified: u64,
+}
+
+/// A node together with its attributes.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Inode {
+    node: FsNode,
+    #[serde(flatten)]
+    attributes: Attributes,
+    /// How many directory entries name the node, not counting `.` and `..`; its slot is vacated
+    /// when the last one goes. Not saved, as the upper layer records every extra name as a hard
+    /// link and the count is rebuilt as they are made again.
+    #[serde(skip)]
+    links: u32,
+}
+
+impl Inode {
+    fn new(node: FsNode) -> Self {
+        let time = now();
+        Self {
+            attributes: Attributes {
+                mode: node.default_mode(),
+                owner: DEFAULT_OWNER.into(),
+                created: time,
+                modified: time,
+            },
+            node,
+            links: 1,
+        }
+    }
+}
+
+const DEFAULT_OWNER: &str = "user";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum FileType {
+    Directory,
+    File,
+    Symlink,
+    CharDevice,
+}
+
+impl FileType {
+    /// The character `ls -l` uses for this type.
+    pub fn indicator(&self) -> char {
+        match self {
+            FileType::Directory => 'd',
+            FileType::File => '-',
+            FileType::Symlink => 'l',
+            FileType::CharDevice => 'c',
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            FileType::Directory => "directory",
+            FileType::File => "regular file",
+            FileType::Symlink => "symbolic link",
+            FileType::CharDevice => "character special file",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Metadata {
+    file_type: FileType,
+    /// Identifies the node, so it is shared by all of its hard links.
+    inode: usize,
+    links: usize,
+    mode: u16,
+    owner: Rc<str>,
+    size: usize,
+    created: u64,
+    modified: u64,
+}
+
+impl Metadata {
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn inode(&self) -> usize {
+        self.inode
+    }
+
+    pub fn links(&self) -> usize {
+        self.links
+    }
+
+    pub fn mode(&self) -> u16 {
+        self.mode
+    }
+
+    pub fn owner(&self) -> Rc<str> {
+        Rc::clone(&self.owner)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    pub fn modified(&self) -> u64 {
+        self.modified
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
+    }
+
+    /// Renders the type and permission bits the way `ls -l` does, e.g. `drwxr-xr-x`.
+    pub fn mode_string(&self) -> String {
+        let mut out = String::from(self.file_type.indicator());
+        for shift in [6, 3, 0] {
+            let bits = self.mode >> shift;
+            out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            out.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug, Serialize, Deserialize)]
+pub struct FsIndex {
+    slot: usize,
+    /// Generation of the slot when this index was handed out. Vacating a slot bumps its
+    /// generation, so indices to a deleted node can't alias whatever reuses the slot.
+    generation: u32,
+}
+
+impl FsIndex {
+    const ROOT: FsIndex = FsIndex {
+        slot: 0,
+        generation: 0,
+    };
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Error, Serialize, Deserialize)]
-pub enum FsError {}
+pub enum FsError {
+    #[error("no such file or directory")]
+    NotFound,
+    #[error("not a directory")]
+    NotADirectory,
+    #[error("is a directory")]
+    IsADirectory,
+    #[error("file exists")]
+    AlreadyExists,
+    #[error("directory not empty")]
+    DirectoryNotEmpty,
+    #[error("invalid name")]
+    InvalidName,
+    #[error("cannot move a directory into itself")]
+    MoveIntoSelf,
+    #[error("not a symbolic link")]
+    NotASymlink,
+    #[error("too many levels of symbolic links")]
+    SymlinkLoop,
+    #[error("invalid mode")]
+    InvalidMode,
+    #[error("permission denied")]
+    PermissionDenied,
+    #[error("stale file handle")]
+    StaleIndex,
+    #[error("is a binary file")]
+    BinaryFile,
+    #[error("no space left on device")]
+    NoSpace,
+    #[error("hard link not allowed for directory")]
+    DirectoryHardLink,
+}
+
+impl FsError {
+    /// Status code reported by programs that fail with this error, borrowed from the matching
+    /// errno values.
+    pub fn status(&self) -> StatusCode {
+        StatusCode(match self {
+            FsError::DirectoryHardLink => 1,
+            FsError::NotFound => 2,
+            FsError::PermissionDenied => 13,
+            FsError::AlreadyExists => 17,
+            FsError::NotADirectory => 20,
+            FsError::IsADirectory => 21,
+            FsError::NoSpace => 28,
+            FsError::InvalidName
+            | FsError::InvalidMode
+            | FsError::MoveIntoSelf
+            | FsError::NotASymlink
+            | FsError::BinaryFile => 22,
+            FsError::SymlinkLoop => 40,
+            FsError::DirectoryNotEmpty => 39,
+            FsError::StaleIndex => 116,
+        })
+    }
+}
+
+/// Maximum number of symbolic links followed while resolving a single path, as on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+fn push_components(pending: &mut Vec<Rc<str>>, path: &Path) -> Result<(), FsError> {
+    for component in path.components().rev() {
+        match component {
+            Component::Normal(name) => {
+                pending.push(name.to_str().ok_or(FsError::InvalidName)?.into())
+            }
+            Component::ParentDir => pending.push("..".into()),
+            Component::RootDir | Component::CurDir => {}
+        }
+    }
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        Err(FsError::InvalidName)
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+struct Slot {
+    generation: u32,
+    inode: Option<Inode>,
+}
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct FsTree {
-    node_table: Vec<Option<FsNode>>,
+    /// Persistent, so cloning the tree shares every node until one side changes it.
+    node_table: Vector<Slot>,
     vacancies: Vec<usize>,
+    /// Bumped on every mutation, so callers can tell whether the tree needs saving.
+    #[serde(skip)]
+    revision: u64,
+    /// The read-only layer beneath this tree, when it is an overlay.
+    #[serde(skip)]
+    lower: Option<Rc<FsTree>>,
+    /// Full-text index of every text file, kept current by the methods that change files.
+    #[serde(skip)]
+    search_index: SearchIndex,
+    #[serde(skip)]
+    quota: Quota,
+    /// Nodes and bytes in use, counted against `quota`.
+    #[serde(skip)]
+    totals: Totals,
 }
 
 impl FsTree {
     pub fn new() -> Self {
         FsTree {
-            node_table: vec![Some(FsNode::Directory(Directory::default()))],
+            node_table: Vector::unit(Slot {
+                generation: FsIndex::ROOT.generation,
+                inode: Some(Inode::new(FsNode::Directory(Directory::default()))),
+            }),
             vacancies: Vec::new(),
+            revision: 0,
+            lower: None,
+            search_index: SearchIndex::default(),
+            quota: Quota::default(),
+            totals: Totals { nodes: 1, bytes: 0 },
         }
     }
 
-    fn get_node(&self, index: FsIndex) -> Option<&FsNode> {
-        self.node_table[index.0].as_ref()
+    fn get_inode(&self, index: FsIndex) -> Result<&Inode, FsError> {
+        self.node_table
+            .get(index.slot)
+            .filter(|slot| slot.generation == index.generation)
+            .and_then(|slot| slot.inode.as_ref())
+            .ok_or(FsError::StaleIndex)
     }
 
-    fn get_node_mut(&mut self, index: FsIndex) -> Option<&mut FsNode> {
-        self.node_table[index.0].as_mut()
+    fn get_inode_mut(&mut self, index: FsIndex) -> Result<&mut Inode, FsError> {
+        self.node_table
+            .get_mut(index.slot)
+            .filter(|slot| slot.generation == index.generation)
+            .and_then(|slot| slot.inode.as_mut())
+            .ok_or(FsError::StaleIndex)
     }
 
-    pub fn root(&self) -> FsIndex {
-        FsIndex(0)
+    fn get_node(&self, index: FsIndex) -> Result<&FsNode, FsError> {
+        self.get_inode(index).map(|inode| &inode.node)
     }
 
-    fn get_entry(&self, name: &str, parent: FsIndex) -> Result<Option<FsIndex>, FsError> {
-        let Some(node) = self.get_node(parent) else {
-            unimplemented!();
-        };
+    fn get_node_mut(&mut self, index: FsIndex) -> Result<&mut FsNode, FsError> {
+        self.get_inode_mut(index).map(|inode| &mut inode.node)
+    }
+
+    /// Number of nodes currently in the tree.
+    pub fn node_count(&self) -> usize {
+        self.node_table
+            .iter()
+            .filter(|slot| slot.inode.is_some())
+            .count()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
 
-        let FsNode::Directory(parent_dir) = node else {
-            unimplemented!();
+    /// Bumps the modification time of a node, e.g. after its contents or entries change.
+    fn touch(&mut self, index: FsIndex) -> Result<(), FsError> {
+        self.get_inode_mut(index)?.attributes.modified = now();
+        self.revision += 1;
+        Ok(())
+    }
+
+    pub fn metadata(&self, index: FsIndex) -> Result<Metadata, FsError> {
+        let inode = self.get_inode(index)?;
+        let links = match &inode.node {
+            // as on Unix, a directory is linked from its parent, by its own `.` and by the `..`
+            // of each subdirectory
+            FsNode::Directory(_) => {
+                2 + self
+                    .iter_children(index)?
+                    .filter(|entry| self.is_directory(entry.index).unwrap_or(false))
+                    .count()
+            }
+            _ => inode.links as usize,
         };
+        Ok(Metadata {
+            file_type: inode.node.file_type(),
+            inode: index.slot,
+            links,
+            mode: inode.attributes.mode,
+            owner: Rc::clone(&inode.attributes.owner),
+            size: inode.node.size(),
+            created: inode.attributes.created,
+            modified: inode.attributes.modified,
+        })
+    }
 
-        Ok(parent_dir.entries.get(name).cloned())
+    /// Replaces the permission bits of a node; the file type is unaffected.
+    pub fn set_mode(&mut self, index: FsIndex, mode: u16) -> Result<(), FsError> {
+        self.get_inode_mut(index)?.attributes.mode = mode & 0o7777;
+        self.revision += 1;
+        Ok(())
     }
 
-    pub fn contents(&self, index: FsIndex) -> Result<Rc<str>, FsError> {
-        match self.get_node(index) {
-            Some(FsNode::Directory(dir)) => unimplemented!(),
-            Some(FsNode::File(file)) => Ok(file.contents()),
-            None => unimplemented!(),
+    fn get_directory(&self, index: FsIndex) -> Result<&Directory, FsError> {
+        match self.get_node(index)? {
+            FsNode::Directory(dir) => Ok(dir),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn get_directory_mut(&mut self, index: FsIndex) -> Result<&mut Directory, FsError> {
+        match self.get_node_mut(index)? {
+            FsNode::Directory(dir) => Ok(dir),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    pub fn root(&self) -> FsIndex {
+        FsIndex::ROOT
+    }
+
+    fn get_entry(&self, name: &str, parent: FsIndex) -> Result<FsIndex, FsError> {
+        self.get_directory(parent)?
+            .entries
+            .get(name)
+            .copied()
+            .ok_or(FsError::NotFound)
+    }
+
+    pub fn contents(&self, index: FsIndex) -> Result<Contents, FsError> {
+        match self.get_node(index)? {
+            FsNode::Directory(_) => Err(FsError::IsADirectory),
+            FsNode::File(file) => Ok(file.contents()),
+            FsNode::Symlink(_) => Err(FsError::SymlinkLoop),
+            FsNode::Generated(generator) if generator.file_type() == FileType::Symlink => {
+                Err(FsError::SymlinkLoop)
+            }
+            FsNode::Generated(generator) => Ok(generator.generate().into()),
+            FsNode::Device(device) => Ok(device.read()),
         }
     }
 
+    /// Reads a file for a program that only works on text, refusing binary files.
+    pub fn read_text(&self, index: FsIndex) -> Result<Rc<str>, FsError> {
+        self.contents(index)?.text()
+    }
+
+    /// Runs a file as a program. Files without any execute bit set are refused.
     pub fn execute(&self, index: FsIndex) -> Result<(Html, StatusCode), FsError> {
-        match self.get_node(index) {
-            Some(FsNode::Directory(dir)) => unimplemented!(),
-            Some(FsNode::File(file)) => Ok(file.execute()),
-            None => unimplemented!(),
+        let inode = self.get_inode(index)?;
+        match &inode.node {
+            FsNode::Directory(_) => Err(FsError::IsADirectory),
+            FsNode::File(_) if inode.attributes.mode & 0o111 == 0 => Err(FsError::PermissionDenied),
+            FsNode::File(file) => file.execute(),
+            FsNode::Symlink(_) => Err(FsError::SymlinkLoop),
+            FsNode::Generated(_) | FsNode::Device(_) => Err(FsError::PermissionDenied),
         }
     }
 
-    pub fn write(&mut self, index: FsIndex, contents: &str) -> Result<(), FsError> {
-        match self.get_node_mut(index) {
-            Some(FsNode::Directory(dir)) => unimplemented!(),
-            Some(FsNode::File(file)) => Ok(file.write(contents)),
-            None => unimplemented!(),
+    pub fn write(&mut self, index: FsIndex, contents: impl Into<Contents>) -> Result<(), FsError> {
+        let contents = contents.into();
+        let size = contents.len();
+        if let FsNode::File(file) = self.get_node(index)? {
+            self.check_write_quota(file.contents.len(), size)?;
+        }
+
+        match self.get_node_mut(index)? {
+            FsNode::Directory(_) => Err(FsError::IsADirectory),
+            FsNode::File(file) => {
+                let old_size = file.contents.len();
+                file.write(contents);
+                self.totals.bytes = self.totals.bytes - old_size + size;
+                self.reindex(index);
+                self.touch(index)
+            }
+            FsNode::Symlink(_) => Err(FsError::SymlinkLoop),
+            FsNode::Generated(_) => Err(FsError::PermissionDenied),
+            FsNode::Device(device) => device.write(&contents),
         }
     }
 
-    pub fn iter_dir(&self, index: FsIndex) -> Result<impl Iterator<Item = DirEntry>, FsError> {
-        match self.get_node(index) {
-            Some(FsNode::Directory(dir)) => Ok(dir.children()),
-            Some(FsNode::File(file)) => unimplemented!(),
-            None => unimplemented!(),
+    /// Adds text to the end of a file, or writes it to a device. Binary files are refused.
+    pub fn append(&mut self, index: FsIndex, text: &str) -> Result<(), FsError> {
+        match self.get_node(index)? {
+            FsNode::File(file) => {
+                let contents = format!("{}{text}", file.contents.text()?);
+                self.write(index, contents.as_str())
+            }
+            _ => self.write(index, text),
         }
     }
 
-    pub fn is_directory(&self, index: FsIndex) -> Result<bool, FsError> {
+    /// Brings the search index up to date with the contents of the node at `index`.
+    fn reindex(&mut self, index: FsIndex) {
         match self.get_node(index) {
-            Some(FsNode::Directory(_)) => Ok(true),
-            Some(FsNode::File(_)) => Ok(false),
-            None => unimplemented!(),
+            Ok(FsNode::File(File {
+                contents: Contents::Text(text),
+            })) => self.search_index.update(index, &Rc::clone(text)),
+            _ => self.search_index.remove(index),
         }
     }
 
+    /// Ranks the text files containing any word of `query`, best match first. Words are
+    /// compared by their stems, so `note` also finds `notes` and `noted`.
+    pub fn search(&self, query: &str) -> Vec<(FsIndex, f64)> {
+        let mut terms = tokens(query).map(|(_, term)| term).collect::<Vec<_>>();
+        terms.sort_unstable();
+        terms.dedup();
+        self.search_index.search(&terms)
+    }
+
+    pub fn iter_dir(&self, index: FsIndex) -> Result<impl Iterator<Item = DirEntry>, FsError> {
+        Ok(self.get_directory(index)?.children())
+    }
+
+    /// Like [`FsTree::iter_dir`], but without the `.` and `..` entries, for walking the tree.
+    pub fn iter_children(&self, index: FsIndex) -> Result<impl Iterator<Item = DirEntry>, FsError> {
+        Ok(self
+            .iter_dir(index)?
+            .filter(|entry| &*entry.name != "." && &*entry.name != ".."))
+    }
+
+    pub fn is_directory(&self, index: FsIndex) -> Result<bool, FsError> {
+        Ok(matches!(self.get_node(index)?, FsNode::Directory(_)))
+    }
+
     pub fn is_file(&self, index: FsIndex) -> Result<bool, FsError> {
-        self.is_directory(index).map(|b| !b)
+        Ok(self.get_node(index)?.file_type() == FileType::File)
     }
 
-    pub fn lookup_path(&self, path: &Path) -> Option<FsIndex> {
-        if path.is_relative() {
-            unimplemented!();
-        }
+    pub fn is_symlink(&self, index: FsIndex) -> Result<bool, FsError> {
+        Ok(self.get_node(index)?.file_type() == FileType::Symlink)
+    }
+
+    pub fn read_link(&self, index: FsIndex) -> Result<Rc<str>, FsError> {
+        self.get_node(index)?
+            .link_target()
+            .ok_or(FsError::NotASymlink)
+    }
+
+    /// Resolves `path` against the directory at `cwd`, returning the node it names along with
+    /// its canonical absolute path. `.`, `..`, repeated slashes and symbolic links are handled
+    /// by walking the tree, and a trailing slash requires the result to be a directory.
+    pub fn resolve(&self, path: &Path, cwd: &Path) -> Result<(FsIndex, PathBuf), FsError> {
+        self.resolve_inner(path, cwd, true)
+    }
+
+    /// Like [`FsTree::resolve`], but a symbolic link in the final component is returned itself
+    /// rather than followed, in the way `lstat(2)` differs from `stat(2)`.
+    pub fn resolve_nofollow(&self, path: &Path, cwd: &Path) -> Result<(FsIndex, PathBuf), FsError> {
+        self.resolve_inner(path, cwd, false)
+    }
+
+    fn resolve_inner(
+        &self,
+        path: &Path,
+        cwd: &Path,
+        follow_final: bool,
+    ) -> Result<(FsIndex, PathBuf), FsError> {
+        let full_path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            Path::new("/").join(cwd).join(path)
+        };
+
+        let path = path.to_string_lossy();
+        let trailing_slash = path.ends_with('/') || path.ends_with("/.");
+        let follow_final = follow_final || trailing_slash;
+
+        // components still to be walked, last to first
+        let mut pending = Vec::new();
+        push_components(&mut pending, &full_path)?;
 
         let mut current = self.root();
-        for component in path.iter().skip(1) {
-            match self
-                .get_entry(component.to_str().unwrap(), current)
-                .unwrap()
-            {
-                Some(next) => current = next,
-                None => return None,
+        let mut canonical = PathBuf::from("/");
+        let mut links_followed = 0;
+        while let Some(name) = pending.pop() {
+            if &*name == ".." {
+                current = self.get_entry("..", current)?;
+                canonical.pop();
+                continue;
             }
+
+            let next = self.get_entry(&name, current)?;
+            match self.get_node(next)?.link_target() {
+                Some(target) if follow_final || !pending.is_empty() => {
+                    links_followed += 1;
+                    if links_followed > MAX_SYMLINKS {
+                        return Err(FsError::SymlinkLoop);
+                    }
+
+                    let target = Path::new(&*target);
+                    if target.is_absolute() {
+                        current = self.root();
+                        canonical = PathBuf::from("/");
+                    }
+                    push_components(&mut pending, target)?;
+                }
+                _ => {
+                    current = next;
+                    canonical.push(&*name);
+                }
+            }
+        }
+
+        if trailing_slash && !self.is_directory(current)? {
+            return Err(FsError::NotADirectory);
         }
 
-        Some(current)
+        Ok((current, canonical))
+    }
+
+    pub fn lookup_path(&self, path: &Path) -> Result<FsIndex, FsError> {
+        self.resolve(path, Path::new("/")).map(|(index, _)| index)
     }
 
     fn vacate(&mut self, index: FsIndex) {
-        self.vacancies.push(index.0)
+        let Ok(bytes) = self.get_node(index).map(FsNode::stored_bytes) else {
+            return;
+        };
+
+        self.totals.nodes -= 1;
+        self.totals.bytes -= bytes;
+        self.search_index.remove(index);
+        let slot = &mut self.node_table[index.slot];
+        slot.inode = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.vacancies.push(index.slot)
     }
 
+    /// Drops one link to a node whose name has been removed, vacating its slot if that was the
+    /// last.
+    fn unlink(&mut self, index: FsIndex) {
+        let Ok(inode) = self.get_inode_mut(index) else {
+            return;
+        };
+        inode.links = inode.links.saturating_sub(1);
+        if inode.links == 0 {
+            self.vacate(index);
+        }
+    }
+
+    /// Returns whether `index` is `ancestor` or lies somewhere beneath it.
+    fn is_descendant(&self, index: FsIndex, ancestor: FsIndex) -> Result<bool, FsError> {
+        let mut current = index;
+        loop {
+            if current == ancestor {
+                return Ok(true);
+            }
+            if current == self.root() {
+                return Ok(false);
+            }
+            current = self.get_entry("..", current)?;
+        }
+    }
+
+    /// Renames `old_name` in `old_parent` to `new_name` in `new_parent`, following `rename(2)`:
+    /// an existing file may be replaced by a file and an existing empty directory by a directory.
     pub fn move_entry(
         &mut self,
         old_name: &str,
@@ -230,130 +868,519 @@ impl FsTree {
         new_name: &str,
         new_parent: FsIndex,
     ) -> Result<(), FsError> {
-        unimplemented!();
-    }
+        validate_name(old_name)?;
+        validate_name(new_name)?;
 
-    pub fn create_directory(&mut self, name: &str, parent: FsIndex) -> Result<FsIndex, FsError> {
-        let vacancy = self.vacancies.last().cloned();
-        let table_len = self.node_table.len();
+        let source = self.get_entry(old_name, old_parent)?;
+        let source_is_dir = self.is_directory(source)?;
+        self.get_directory(new_parent)?;
 
-        let Some(node) = self.get_node_mut(parent) else {
-            unimplemented!();
-        };
+        if source_is_dir && self.is_descendant(new_parent, source)? {
+            return Err(FsError::MoveIntoSelf);
+        }
+        self.check_move_quota(source, old_parent, new_parent)?;
 
-        let FsNode::Directory(parent_dir) = node else {
-            unimplemented!();
-        };
+        match self.get_entry(new_name, new_parent) {
+            Ok(target) if target == source => return Ok(()),
+            Ok(target) => match (source_is_dir, self.get_node(target)?) {
+                (false, FsNode::Directory(_)) => return Err(FsError::IsADirectory),
+                (true, FsNode::Directory(dir)) if !dir.is_empty() => {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+                (true, FsNode::Directory(_)) | (false, _) => self.unlink(target),
+                (true, _) => return Err(FsError::NotADirectory),
+            },
+            Err(FsError::NotFound) => {}
+            Err(error) => return Err(error),
+        }
+
+        self.get_directory_mut(old_parent)?.entries.remove(old_name);
+        self.get_directory_mut(new_parent)?
+            .entries
+            .insert(new_name.into(), source);
+
+        if source_is_dir {
+            self.get_directory_mut(source)?
+                .entries
+                .insert("..".into(), new_parent);
+        }
+
+        self.touch(old_parent)?;
+        self.touch(new_parent)
+    }
+
+    fn insert_node(
+        &mut self,
+        name: &str,
+        parent: FsIndex,
+        make_node: impl FnOnce(FsIndex) -> FsNode,
+    ) -> Result<FsIndex, FsError> {
+        validate_name(name)?;
+
+        let vacancy = self.vacancies.last().map(|&slot| FsIndex {
+            slot,
+            generation: self.node_table[slot].generation,
+        });
+        let table_len = self.node_table.len();
 
-        if parent_dir.entries.contains_key(name) {
-            unimplemented!();
+        if self.get_directory(parent)?.entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
         }
+        self.check_node_quota(parent)?;
 
-        match vacancy {
-            Some(v) => {
-                let node_index = FsIndex(v);
+        let parent_dir = self.get_directory_mut(parent)?;
+
+        let node_index = match vacancy {
+            Some(node_index) => {
                 parent_dir.entries.insert(name.into(), node_index);
-                self.node_table[v] = Some(FsNode::Directory(Directory::new(parent, node_index)));
+                self.node_table[node_index.slot].inode = Some(Inode::new(make_node(node_index)));
                 self.vacancies.pop();
-                Ok(node_index)
+                node_index
             }
             None => {
-                let node_index = FsIndex(table_len);
+                let node_index = FsIndex {
+                    slot: table_len,
+                    generation: 0,
+                };
                 parent_dir.entries.insert(name.into(), node_index);
-                self.node_table
-                    .push(Some(FsNode::Directory(Directory::new(parent, node_index))));
-                Ok(node_index)
+                self.node_table.push_back(Slot {
+                    generation: node_index.generation,
+                    inode: Some(Inode::new(make_node(node_index))),
+                });
+                node_index
             }
-        }
+        };
+
+        // nodes restored from an upper layer arrive with their contents
+        self.totals.nodes += 1;
+        self.totals.bytes += self.get_node(node_index)?.stored_bytes();
+        self.touch(parent)?;
+        Ok(node_index)
+    }
+
+    pub fn create_directory(&mut self, name: &str, parent: FsIndex) -> Result<FsIndex, FsError> {
+        self.insert_node(name, parent, |this| {
+            FsNode::Directory(Directory::new(parent, this))
+        })
     }
 
     pub fn create_file(&mut self, name: &str, parent: FsIndex) -> Result<FsIndex, FsError> {
-        let vacancy = self.vacancies.last().cloned();
-        let table_len = self.node_table.len();
+        let index = self.insert_node(name, parent, |_| FsNode::File(File::new()))?;
+        self.reindex(index);
+        Ok(index)
+    }
 
-        let Some(node) = self.get_node_mut(parent) else {
-            unimplemented!();
-        };
+    pub fn create_symlink(
+        &mut self,
+        name: &str,
+        parent: FsIndex,
+        target: &str,
+    ) -> Result<FsIndex, FsError> {
+        if target.is_empty() {
+            return Err(FsError::NotFound);
+        }
 
-        let FsNode::Directory(parent_dir) = node else {
-            unimplemented!();
-        };
+        self.insert_node(name, parent, |_| {
+            FsNode::Symlink(Symlink {
+                target: target.into(),
+            })
+        })
+    }
 
-        if parent_dir.entries.contains_key(name) {
-            unimplemented!();
-        }
+    /// Creates a read-only node whose contents come from `generator` each time it is read.
+    pub fn create_generated(
+        &mut self,
+        name: &str,
+        parent: FsIndex,
+        generator: Generator,
+    ) -> Result<FsIndex, FsError> {
+        self.insert_node(name, parent, |_| FsNode::Generated(generator))
+    }
 
-        match vacancy {
-            Some(v) => {
-                let node_index = FsIndex(v);
-                parent_dir.entries.insert(name.into(), node_index);
-                self.node_table[v] = Some(FsNode::File(File::new()));
-                self.vacancies.pop();
-                Ok(node_index)
-            }
-            None => {
-                let node_index = FsIndex(table_len);
-                parent_dir.entries.insert(name.into(), node_index);
-                self.node_table.push(Some(FsNode::File(File::new())));
-                Ok(node_index)
-            }
+    fn create_device(
+        &mut self,
+        name: &str,
+        parent: FsIndex,
+        device: Device,
+    ) -> Result<FsIndex, FsError> {
+        self.insert_node(name, parent, |_| FsNode::Device(device))
+    }
+
+    /// Adds `name` in `parent` as another name for the node at `target`, like `link(2)`. The
+    /// node is only removed once every name is. Directories can't be linked, since each has a
+    /// single parent.
+    pub fn create_hard_link(
+        &mut self,
+        name: &str,
+        parent: FsIndex,
+        target: FsIndex,
+    ) -> Result<(), FsError> {
+        validate_name(name)?;
+        if self.is_directory(target)? {
+            return Err(FsError::DirectoryHardLink);
+        }
+        if self.get_directory(parent)?.entries.contains_key(name) {
+            return Err(FsError::AlreadyExists);
         }
+        self.check_depth_quota(parent)?;
+
+        self.get_directory_mut(parent)?
+            .entries
+            .insert(name.into(), target);
+        self.get_inode_mut(target)?.links += 1;
+        self.touch(parent)
     }
 
+    /// Removes a file, symbolic link or empty directory from `parent`, like `unlink(2)` and
+    /// `rmdir(2)` combined.
     pub fn delete(&mut self, name: &str, parent: FsIndex) -> Result<(), FsError> {
-        let Some(node) = self.get_node_mut(parent) else {
-            unimplemented!();
-        };
-
-        let FsNode::Directory(parent_dir) = node else {
-            unimplemented!();
-        };
+        validate_name(name)?;
 
-        let Some(removal_index) = parent_dir.entries.remove(name) else {
-            unimplemented!();
-        };
+        let removal_index = self.get_entry(name, parent)?;
+        if let FsNode::Directory(dir) = self.get_node(removal_index)?
+            && !dir.is_empty()
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
 
-        self.vacate(removal_index);
+        self.get_directory_mut(parent)?.entries.remove(name);
+        self.unlink(removal_index);
 
-        Ok(())
+        self.touch(parent)
     }
 
+    /// Removes an entry from `parent` along with everything beneath it.
     pub fn delete_recursive(&mut self, name: &str, parent: FsIndex) -> Result<(), FsError> {
-        let Some(node) = self.get_node_mut(parent) else {
-            unimplemented!();
-        };
+        validate_name(name)?;
 
-        let FsNode::Directory(parent_dir) = node else {
-            unimplemented!();
-        };
-
-        let Some(removal_index) = parent_dir.entries.get(name).cloned() else {
-            unimplemented!();
-        };
+        let removal_index = self.get_entry(name, parent)?;
+        self.get_directory_mut(parent)?.entries.remove(name);
 
-        if let FsNode::Directory(d) = self.get_node(removal_index).unwrap() {
-            let names = d.entries.keys().map(Rc::clone).collect::<Vec<_>>();
-            for name in names {
-                self.delete_recursive(&name, removal_index).expect("a");
+        let mut pending = vec![removal_index];
+        while let Some(index) = pending.pop() {
+            if let Ok(children) = self.iter_children(index) {
+                pending.extend(children.map(|entry| entry.index));
             }
+            self.unlink(index);
         }
 
-        let FsNode::Directory(parent_dir) = self.get_node_mut(parent).unwrap() else {
-            panic!();
-        };
-        parent_dir.entries.remove(name).expect("a");
-        self.vacate(removal_index);
-
-        Ok(())
+        self.touch(parent)
     }
 }
 
 impl Default for FsTree {
     fn default() -> Self {
         let mut fs_tree = FsTree::new();
-        let mut current = fs_tree.create_directory("home", fs_tree.root()).unwrap();
-        current = fs_tree.create_directory("user", current).unwrap();
+        content::populate(&mut fs_tree).expect("embedded content should form a valid tree");
+        device::mount(&mut fs_tree).expect("/dev should fit alongside the embedded content");
+        proc::mount(&mut fs_tree).expect("/proc should fit alongside the embedded content");
+
+        fs_tree
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use unix_path::Path;
+
+    use super::{DateTime, FsError, FsIndex, FsTree, MAX_SYMLINKS};
+
+    fn lookup(fs_tree: &FsTree, path: &str) -> FsIndex {
+        fs_tree.lookup_path(Path::new(path)).unwrap()
+    }
+
+    /// `/a` holding a file and a subdirectory, and an empty `/b`.
+    fn tree() -> FsTree {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        let a = fs_tree.create_directory("a", root).unwrap();
+        fs_tree.create_file("file", a).unwrap();
+        fs_tree.create_directory("sub", a).unwrap();
+        fs_tree.create_directory("b", root).unwrap();
         fs_tree
     }
+
+    #[test]
+    fn breaks_down_utc_times() {
+        let utc = |millis| {
+            let time = DateTime::utc(millis);
+            (
+                (time.year, time.month, time.day),
+                (time.hour, time.minute, time.second, time.millisecond),
+            )
+        };
+        assert_eq!(utc(0), ((1970, 1, 1), (0, 0, 0, 0)));
+        assert_eq!(utc(951_782_400_000), ((2000, 2, 29), (0, 0, 0, 0)));
+        assert_eq!(utc(1_700_000_000_123), ((2023, 11, 14), (22, 13, 20, 123)));
+        assert_eq!(utc(1_735_689_599_999), ((2024, 12, 31), (23, 59, 59, 999)));
+    }
+
+    #[test]
+    fn index_goes_stale_once_its_slot_is_reused() {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        let old = fs_tree.create_file("old", root).unwrap();
+        fs_tree.write(old, "old contents").unwrap();
+        fs_tree.delete("old", root).unwrap();
+        assert_eq!(fs_tree.contents(old), Err(FsError::StaleIndex));
+
+        let new = fs_tree.create_file("new", root).unwrap();
+        assert_eq!(new.slot, old.slot);
+        assert_ne!(new.generation, old.generation);
+        assert_eq!(fs_tree.contents(old), Err(FsError::StaleIndex));
+        assert_eq!(fs_tree.write(old, "clobbered"), Err(FsError::StaleIndex));
+        assert_eq!(fs_tree.metadata(old).map(|_| ()), Err(FsError::StaleIndex));
+        assert_eq!(&*fs_tree.read_text(new).unwrap(), "");
+    }
+
+    #[test]
+    fn delete_refuses_a_non_empty_directory() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let before = (fs_tree.node_count(), fs_tree.totals);
+        assert_eq!(fs_tree.delete("a", root), Err(FsError::DirectoryNotEmpty));
+        assert_eq!((fs_tree.node_count(), fs_tree.totals), before);
+
+        fs_tree.delete("b", root).unwrap();
+        assert_eq!(fs_tree.node_count(), before.0 - 1);
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn delete_recursive_frees_the_whole_subtree() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let before = fs_tree.totals;
+        let vacancies = fs_tree.vacancies.clone();
+
+        let a = lookup(&fs_tree, "/a");
+        let file = lookup(&fs_tree, "/a/file");
+        fs_tree.write(file, "some bytes").unwrap();
+        let deep = fs_tree
+            .create_directory("deep", lookup(&fs_tree, "/a/sub"))
+            .unwrap();
+        fs_tree.create_file("leaf", deep).unwrap();
+        let mut subtree = [a, file, deep]
+            .into_iter()
+            .chain(["/a/sub", "/a/sub/deep/leaf"].map(|path| lookup(&fs_tree, path)))
+            .map(|index| index.slot)
+            .collect::<Vec<_>>();
+
+        fs_tree.delete_recursive("a", root).unwrap();
+        let mut freed = fs_tree.vacancies[vacancies.len()..].to_vec();
+        freed.sort_unstable();
+        subtree.sort_unstable();
+        assert_eq!(freed, subtree);
+        assert_eq!(fs_tree.totals.nodes, before.nodes - 3);
+        assert_eq!(fs_tree.totals.bytes, 0);
+        assert_eq!(fs_tree.usage().nodes, fs_tree.totals.nodes);
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn move_replaces_an_empty_directory() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let a = lookup(&fs_tree, "/a");
+        let nodes = fs_tree.node_count();
+
+        fs_tree.move_entry("a", root, "b", root).unwrap();
+        assert_eq!(lookup(&fs_tree, "/b"), a);
+        assert_eq!(lookup(&fs_tree, "/b/sub/.."), a);
+        assert_eq!(fs_tree.lookup_path(Path::new("/a")), Err(FsError::NotFound));
+        assert_eq!(fs_tree.node_count(), nodes - 1);
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn move_refuses_to_replace_a_non_empty_directory() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        assert_eq!(
+            fs_tree.move_entry("b", root, "a", root),
+            Err(FsError::DirectoryNotEmpty)
+        );
+
+        let a = lookup(&fs_tree, "/a");
+        assert_eq!(
+            fs_tree.move_entry("file", a, "sub", a),
+            Err(FsError::IsADirectory)
+        );
+        assert_eq!(
+            fs_tree.move_entry("sub", a, "file", a),
+            Err(FsError::NotADirectory)
+        );
+        assert!(fs_tree.is_directory(lookup(&fs_tree, "/a/sub")).unwrap());
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn move_refuses_to_put_a_directory_inside_itself() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let a = lookup(&fs_tree, "/a");
+        let sub = lookup(&fs_tree, "/a/sub");
+        assert_eq!(
+            fs_tree.move_entry("a", root, "a", a),
+            Err(FsError::MoveIntoSelf)
+        );
+        assert_eq!(
+            fs_tree.move_entry("a", root, "deeper", sub),
+            Err(FsError::MoveIntoSelf)
+        );
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn move_points_parent_entry_at_new_parent() {
+        let mut fs_tree = tree();
+        let a = lookup(&fs_tree, "/a");
+        let b = lookup(&fs_tree, "/b");
+        let sub = lookup(&fs_tree, "/a/sub");
+
+        fs_tree.move_entry("sub", a, "moved", b).unwrap();
+        assert_eq!(lookup(&fs_tree, "/b/moved"), sub);
+        assert_eq!(lookup(&fs_tree, "/b/moved/.."), b);
+        assert_eq!(fs_tree.iter_children(a).unwrap().count(), 1);
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn resolve_follows_shipped_symlink() {
+        let fs_tree = FsTree::default();
+        let (resume, canonical) = fs_tree
+            .resolve(Path::new("resume"), Path::new("/home/user"))
+            .unwrap();
+        assert!(fs_tree.is_file(resume).unwrap());
+        assert_eq!(canonical, Path::new("/srv/docs/resume.md"));
+
+        let (link, _) = fs_tree
+            .resolve_nofollow(Path::new("/home/user/resume"), Path::new("/"))
+            .unwrap();
+        assert_eq!(&*fs_tree.read_link(link).unwrap(), "/srv/docs/resume.md");
+    }
+
+    #[test]
+    fn resolve_gives_up_on_symlink_loops() {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        fs_tree.create_symlink("ping", root, "pong").unwrap();
+        fs_tree.create_symlink("pong", root, "/ping").unwrap();
+        assert_eq!(
+            fs_tree.lookup_path(Path::new("/ping")),
+            Err(FsError::SymlinkLoop)
+        );
+        // the link itself can still be looked at
+        assert!(
+            fs_tree
+                .resolve_nofollow(Path::new("/ping"), Path::new("/"))
+                .is_ok()
+        );
+
+        // a chain of exactly MAX_SYMLINKS links resolves; one more is too many
+        fs_tree.create_file("end", root).unwrap();
+        fs_tree.create_symlink("link0", root, "end").unwrap();
+        for i in 1..=MAX_SYMLINKS {
+            let target = format!("link{}", i - 1);
+            fs_tree
+                .create_symlink(&format!("link{i}"), root, &target)
+                .unwrap();
+        }
+        let end = lookup(&fs_tree, "/end");
+        assert_eq!(
+            fs_tree.lookup_path(Path::new(&format!("/link{}", MAX_SYMLINKS - 1))),
+            Ok(end)
+        );
+        assert_eq!(
+            fs_tree.lookup_path(Path::new(&format!("/link{MAX_SYMLINKS}"))),
+            Err(FsError::SymlinkLoop)
+        );
+    }
+
+    #[test]
+    fn resolve_requires_a_directory_before_a_trailing_slash() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        fs_tree.create_symlink("to_a", root, "a").unwrap();
+        fs_tree.create_symlink("to_file", root, "a/file").unwrap();
+        let a = lookup(&fs_tree, "/a");
+        let cwd = Path::new("/");
+
+        assert_eq!(
+            fs_tree.resolve(Path::new("a/file/"), cwd),
+            Err(FsError::NotADirectory)
+        );
+        assert_eq!(
+            fs_tree.resolve(Path::new("a/file/."), cwd),
+            Err(FsError::NotADirectory)
+        );
+        assert_eq!(
+            fs_tree.resolve_nofollow(Path::new("to_file/"), cwd),
+            Err(FsError::NotADirectory)
+        );
+        // a trailing slash follows a final link even when links otherwise aren't
+        let (index, canonical) = fs_tree.resolve_nofollow(Path::new("to_a/"), cwd).unwrap();
+        assert_eq!((index, canonical.as_path()), (a, Path::new("/a")));
+        let (index, _) = fs_tree.resolve_nofollow(Path::new("to_a"), cwd).unwrap();
+        assert!(fs_tree.is_symlink(index).unwrap());
+    }
+
+    #[test]
+    fn hard_link_keeps_its_node_until_the_last_name_goes() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let (a, b) = (lookup(&fs_tree, "/a"), lookup(&fs_tree, "/b"));
+        let file = lookup(&fs_tree, "/a/file");
+        fs_tree.write(file, "shared").unwrap();
+        let vacancies = fs_tree.vacancies.len();
+
+        fs_tree.create_hard_link("again", b, file).unwrap();
+        assert_eq!(lookup(&fs_tree, "/b/again"), file);
+        assert_eq!(fs_tree.metadata(file).unwrap().links(), 2);
+
+        fs_tree.delete("file", a).unwrap();
+        assert_eq!(fs_tree.vacancies.len(), vacancies);
+        assert_eq!(fs_tree.metadata(file).unwrap().links(), 1);
+        assert_eq!(&*fs_tree.read_text(file).unwrap(), "shared");
+        assert_eq!(fs_tree.check(), []);
+
+        fs_tree.delete("again", b).unwrap();
+        assert_eq!(fs_tree.vacancies[vacancies..], [file.slot]);
+        assert_eq!(fs_tree.read_text(file), Err(FsError::StaleIndex));
+        assert_eq!(fs_tree.check(), []);
+
+        // moving a name doesn't count as removing it
+        let file = fs_tree.create_file("file", root).unwrap();
+        fs_tree.create_hard_link("again", root, file).unwrap();
+        fs_tree.move_entry("again", root, "moved", b).unwrap();
+        assert_eq!(fs_tree.metadata(file).unwrap().links(), 2);
+    }
+
+    #[test]
+    fn hard_links_are_charged_once() {
+        let mut fs_tree = tree();
+        let b = lookup(&fs_tree, "/b");
+        let file = lookup(&fs_tree, "/a/file");
+        fs_tree.write(file, "0123456789").unwrap();
+        let before = fs_tree.totals;
+
+        fs_tree.create_hard_link("one", b, file).unwrap();
+        fs_tree.create_hard_link("two", b, file).unwrap();
+        assert_eq!(fs_tree.totals, before);
+        fs_tree.write(file, "01234").unwrap();
+        assert_eq!(fs_tree.totals.bytes, before.bytes - 5);
+        assert_eq!(fs_tree.usage().nodes, before.nodes);
+    }
+
+    #[test]
+    fn directories_cannot_be_hard_linked() {
+        let mut fs_tree = tree();
+        let (a, b) = (lookup(&fs_tree, "/a"), lookup(&fs_tree, "/b"));
+        assert_eq!(
+            fs_tree.create_hard_link("a", b, a),
+            Err(FsError::DirectoryHardLink)
+        );
+        assert_eq!(fs_tree.metadata(a).unwrap().links(), 3);
+        assert_eq!(fs_tree.check(), []);
+    }
 }
@@ -0,0 +1,374 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use super::{FsError, FsIndex, FsNode, FsTree};
+
+/// Directory that orphaned nodes are reattached to by [`FsTree::repair`].
+const LOST_AND_FOUND: &str = "lost+found";
+
+/// A broken invariant found by [`FsTree::check`].
+#[derive(Clone, PartialEq, Eq, Debug, Error)]
+pub enum Violation {
+    #[error("slot {slot} holds a node that is not reachable from /")]
+    Orphan { slot: usize },
+    #[error("`..` in {path} does not point at its parent")]
+    WrongParent { path: String },
+    #[error("`.` in {path} does not point at itself")]
+    WrongSelf { path: String },
+    #[error("{path} refers to a deleted node")]
+    DanglingEntry { path: String },
+    #[error("{path} is an extra link to a directory that is already linked elsewhere")]
+    ExtraDirectoryLink { path: String },
+    #[error("{path} lives in slot {slot}, which is listed as vacant")]
+    VacantSlotInUse { path: String, slot: usize },
+    #[error("slot {slot} is listed as vacant more than once")]
+    DuplicateVacancy { slot: usize },
+    #[error("slot {slot} is empty but not listed as vacant")]
+    LeakedSlot { slot: usize },
+    #[error("{path} has a link count of {recorded} but {names} names")]
+    WrongLinkCount {
+        path: String,
+        recorded: u32,
+        names: u32,
+    },
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent.ends_with('/') {
+        format!("{parent}{name}")
+    } else {
+        format!("{parent}/{name}")
+    }
+}
+
+/// The entries of a directory other than `.` and `..`, in name order.
+fn sorted_children(fs_tree: &FsTree, dir: FsIndex) -> Vec<(Rc<str>, FsIndex)> {
+    let mut children = fs_tree
+        .iter_children(dir)
+        .map(|children| {
+            children
+                .map(|entry| (entry.name(), entry.index()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    children.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    children
+}
+
+impl FsTree {
+    /// Verifies the structural invariants of the tree: every live node is reachable from the
+    /// root, `.` and `..` point at the right directories, directory entries only refer to live
+    /// nodes, link counts match the names of each node, and the vacancy list matches the empty
+    /// slots exactly.
+    pub fn check(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        let mut vacant = HashSet::new();
+        for &slot in &self.vacancies {
+            if !vacant.insert(slot) {
+                violations.push(Violation::DuplicateVacancy { slot });
+            }
+        }
+
+        let root = self.root();
+        let mut reachable = HashSet::from([root.slot]);
+        // the names of every node other than a directory, and the first path found to each
+        let mut names = HashMap::<FsIndex, (u32, String)>::new();
+        let mut queue = VecDeque::from([(root, root, String::from("/"))]);
+        while let Some((dir_index, parent, path)) = queue.pop_front() {
+            let Ok(dir) = self.get_directory(dir_index) else {
+                continue;
+            };
+            if dir.entries.get(".") != Some(&dir_index) {
+                violations.push(Violation::WrongSelf { path: path.clone() });
+            }
+            if dir.entries.get("..") != Some(&parent) {
+                violations.push(Violation::WrongParent { path: path.clone() });
+            }
+
+            for (name, child) in sorted_children(self, dir_index) {
+                let path = child_path(&path, &name);
+                let Ok(node) = self.get_node(child) else {
+                    violations.push(Violation::DanglingEntry { path });
+                    continue;
+                };
+
+                if vacant.contains(&child.slot) {
+                    violations.push(Violation::VacantSlotInUse {
+                        path: path.clone(),
+                        slot: child.slot,
+                    });
+                }
+
+                let first_visit = reachable.insert(child.slot);
+                if let FsNode::Directory(_) = node {
+                    if first_visit {
+                        queue.push_back((child, dir_index, path));
+                    } else {
+                        violations.push(Violation::ExtraDirectoryLink { path });
+                    }
+                } else {
+                    names.entry(child).or_insert((0, path)).0 += 1;
+                }
+            }
+        }
+
+        let mut names = names.into_iter().collect::<Vec<_>>();
+        names.sort_unstable_by(|(_, (_, a)), (_, (_, b))| a.cmp(b));
+        for (index, (names, path)) in names {
+            let recorded = self.get_inode(index).map_or(0, |inode| inode.links);
+            if recorded != names {
+                violations.push(Violation::WrongLinkCount {
+                    path,
+                    recorded,
+                    names,
+                });
+            }
+        }
+
+        for (slot, contents) in self.node_table.iter().enumerate() {
+            match contents.inode {
+                Some(_) if !reachable.contains(&slot) => {
+                    violations.push(Violation::Orphan { slot });
+                }
+                None if !vacant.contains(&slot) => violations.push(Violation::LeakedSlot { slot }),
+                _ => {}
+            }
+        }
+
+        violations
+    }
+
+    /// Fixes every violation [`FsTree::check`] reports, returning what was found. Dangling and
+    /// extra directory entries are dropped, `.` and `..` are rewritten, orphaned nodes are
+    /// reattached under `/lost+found` as `#<slot>`, and link counts and the vacancy list are
+    /// rebuilt.
+    pub fn repair(&mut self) -> Result<Vec<Violation>, FsError> {
+        let violations = self.check();
+        if violations.is_empty() {
+            return Ok(violations);
+        }
+
+        // rebuild vacancies first so nothing allocated below can land on a live node
+        self.vacancies = (0..self.node_table.len())
+            .rev()
+            .filter(|&slot| self.node_table[slot].inode.is_none())
+            .collect();
+        self.recount();
+
+        loop {
+            let reachable = self.repair_reachable()?;
+            let orphans = self
+                .node_table
+                .iter()
+                .enumerate()
+                .filter(|(slot, contents)| contents.inode.is_some() && !reachable.contains(slot))
+                .map(|(slot, contents)| FsIndex {
+                    slot,
+                    generation: contents.generation,
+                })
+                .collect::<Vec<_>>();
+            if orphans.is_empty() {
+                break;
+            }
+
+            // only reattach orphans that no other orphan links to; their children come along
+            let linked = orphans
+                .iter()
+                .flat_map(|&orphan| sorted_children(self, orphan))
+                .map(|(_, child)| child.slot)
+                .collect::<HashSet<_>>();
+            let mut roots = orphans
+                .iter()
+                .copied()
+                .filter(|orphan| !linked.contains(&orphan.slot))
+                .collect::<Vec<_>>();
+            if roots.is_empty() {
+                // every orphan is linked from another orphan, so they form a cycle
+                roots.push(orphans[0]);
+            }
+
+            let lost_and_found = match self.get_entry(LOST_AND_FOUND, self.root()) {
+                Ok(index) => index,
+                Err(FsError::NotFound) => self.create_directory(LOST_AND_FOUND, self.root())?,
+                Err(error) => return Err(error),
+            };
+            let dir = self.get_directory_mut(lost_and_found)?;
+            for root in roots {
+                dir.entries.insert(format!("#{}", root.slot).into(), root);
+            }
+        }
+
+        self.recount_links();
+        self.revision += 1;
+        Ok(violations)
+    }
+
+    /// Sets the link count of every node to the number of entries naming it. Run once every
+    /// node is reachable, so none is left at zero.
+    fn recount_links(&mut self) {
+        let mut names = HashMap::<FsIndex, u32>::new();
+        let mut pending = vec![self.root()];
+        while let Some(dir_index) = pending.pop() {
+            for (_, child) in sorted_children(self, dir_index) {
+                if self.is_directory(child).unwrap_or(false) {
+                    pending.push(child);
+                }
+                *names.entry(child).or_default() += 1;
+            }
+        }
+
+        for (index, names) in names {
+            if let Ok(inode) = self.get_inode_mut(index) {
+                inode.links = names;
+            }
+        }
+    }
+
+    /// Walks everything reachable from the root, dropping entries to dead nodes and extra links
+    /// to directories and rewriting `.` and `..`. Returns the slots that were reached.
+    fn repair_reachable(&mut self) -> Result<HashSet<usize>, FsError> {
+        let root = self.root();
+        let mut reachable = HashSet::from([root.slot]);
+        let mut queue = VecDeque::from([(root, root)]);
+        while let Some((dir_index, parent)) = queue.pop_front() {
+            let mut removals = Vec::new();
+            for (name, child) in sorted_children(self, dir_index) {
+                match self.get_node(child) {
+                    Err(_) => removals.push(name),
+                    Ok(FsNode::Directory(_)) => {
+                        if reachable.insert(child.slot) {
+                            queue.push_back((child, dir_index));
+                        } else {
+                            removals.push(name);
+                        }
+                    }
+                    Ok(_) => {
+                        reachable.insert(child.slot);
+                    }
+                }
+            }
+
+            let dir = self.get_directory_mut(dir_index)?;
+            for name in removals {
+                dir.entries.remove(&name);
+            }
+            dir.entries.insert(".".into(), dir_index);
+            dir.entries.insert("..".into(), parent);
+        }
+
+        Ok(reachable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unix_path::Path;
+
+    use super::{LOST_AND_FOUND, Violation};
+    use crate::fs::{FsIndex, FsTree};
+
+    fn lookup(fs_tree: &FsTree, path: &str) -> FsIndex {
+        fs_tree.lookup_path(Path::new(path)).unwrap()
+    }
+
+    /// `/a/sub` and `/a/file`.
+    fn tree() -> FsTree {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        let a = fs_tree.create_directory("a", root).unwrap();
+        fs_tree.create_directory("sub", a).unwrap();
+        fs_tree.create_file("file", a).unwrap();
+        fs_tree
+    }
+
+    #[test]
+    fn clean_tree_has_no_violations() {
+        assert_eq!(tree().check(), []);
+        assert_eq!(FsTree::default().check(), []);
+    }
+
+    #[test]
+    fn reattaches_orphans_under_lost_and_found() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let a = lookup(&fs_tree, "/a");
+        let file = lookup(&fs_tree, "/a/file");
+        let sub = lookup(&fs_tree, "/a/sub");
+        fs_tree.get_directory_mut(root).unwrap().entries.remove("a");
+
+        let mut orphans = vec![a.slot, sub.slot, file.slot];
+        orphans.sort_unstable();
+        let expected = orphans
+            .into_iter()
+            .map(|slot| Violation::Orphan { slot })
+            .collect::<Vec<_>>();
+        assert_eq!(fs_tree.check(), expected);
+
+        assert_eq!(fs_tree.repair().unwrap(), expected);
+        let found = format!("/{LOST_AND_FOUND}/#{}", a.slot);
+        assert_eq!(lookup(&fs_tree, &found), a);
+        assert_eq!(lookup(&fs_tree, &format!("{found}/file")), file);
+        assert_eq!(
+            lookup(&fs_tree, &format!("{found}/..")),
+            lookup(&fs_tree, &format!("/{LOST_AND_FOUND}"))
+        );
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn rewrites_a_wrong_parent() {
+        let mut fs_tree = tree();
+        let root = fs_tree.root();
+        let sub = lookup(&fs_tree, "/a/sub");
+        fs_tree
+            .get_directory_mut(sub)
+            .unwrap()
+            .entries
+            .insert("..".into(), root);
+
+        let expected = [Violation::WrongParent {
+            path: "/a/sub".into(),
+        }];
+        assert_eq!(fs_tree.check(), expected);
+        assert_eq!(fs_tree.repair().unwrap(), expected);
+        assert_eq!(lookup(&fs_tree, "/a/sub/.."), lookup(&fs_tree, "/a"));
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn rebuilds_a_duplicate_vacancy() {
+        let mut fs_tree = tree();
+        let a = lookup(&fs_tree, "/a");
+        let file = lookup(&fs_tree, "/a/file");
+        fs_tree.delete("file", a).unwrap();
+        fs_tree.vacancies.push(file.slot);
+
+        let expected = [Violation::DuplicateVacancy { slot: file.slot }];
+        assert_eq!(fs_tree.check(), expected);
+        assert_eq!(fs_tree.repair().unwrap(), expected);
+        assert_eq!(fs_tree.vacancies, [file.slot]);
+        assert_eq!(fs_tree.check(), []);
+    }
+
+    #[test]
+    fn recounts_a_wrong_link_count() {
+        let mut fs_tree = tree();
+        let file = lookup(&fs_tree, "/a/file");
+        fs_tree.get_inode_mut(file).unwrap().links = 3;
+
+        let expected = [Violation::WrongLinkCount {
+            path: "/a/file".into(),
+            recorded: 3,
+            names: 1,
+        }];
+        assert_eq!(fs_tree.check(), expected);
+        assert_eq!(fs_tree.repair().unwrap(), expected);
+        assert_eq!(fs_tree.metadata(file).unwrap().links(), 1);
+        assert_eq!(fs_tree.check(), []);
+    }
+}
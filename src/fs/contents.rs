@@ -0,0 +1,156 @@
+use std::rc::Rc;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use super::FsError;
+
+/// What a file holds. Text is kept apart from arbitrary bytes so that programs working on text
+/// can refuse binary files rather than print garbage.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(from = "StoredContents", into = "StoredContents")]
+pub enum Contents {
+    Text(Rc<str>),
+    Binary(Rc<[u8]>),
+}
+
+/// The stored form of [`Contents`]. Text stays a bare string, as it was before files could hold
+/// bytes, so trees saved by older versions still load.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredContents {
+    Text(Rc<str>),
+    Binary {
+        #[serde(with = "base64_bytes")]
+        base64: Rc<[u8]>,
+    },
+}
+
+impl From<StoredContents> for Contents {
+    fn from(stored: StoredContents) -> Self {
+        match stored {
+            StoredContents::Text(text) => Contents::Text(text),
+            StoredContents::Binary { base64 } => Contents::Binary(base64),
+        }
+    }
+}
+
+impl From<Contents> for StoredContents {
+    fn from(contents: Contents) -> Self {
+        match contents {
+            Contents::Text(text) => StoredContents::Text(text),
+            Contents::Binary(bytes) => StoredContents::Binary { base64: bytes },
+        }
+    }
+}
+
+/// Stores bytes as a base64 string, which takes far less room in JSON than an array of numbers.
+mod base64_bytes {
+    use std::rc::Rc;
+
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &Rc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rc<[u8]>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(encoded)
+            .map(Rc::from)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Leading bytes that identify the image formats browsers can display.
+const IMAGE_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"\0\0\x01\0", "image/x-icon"),
+];
+
+impl Contents {
+    /// Classifies raw bytes: anything that is valid UTF-8 without NUL bytes counts as text.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => Contents::Text(text.into()),
+            _ => Contents::Binary(bytes.into()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Contents::Text(text) => text.as_bytes(),
+            Contents::Binary(bytes) => bytes,
+        }
+    }
+
+    /// The contents as text, refusing binary data.
+    pub fn text(&self) -> Result<Rc<str>, FsError> {
+        match self {
+            Contents::Text(text) => Ok(Rc::clone(text)),
+            Contents::Binary(_) => Err(FsError::BinaryFile),
+        }
+    }
+
+    /// The MIME type of the image these contents hold, if they are one the browser can show.
+    pub fn image_type(&self) -> Option<&'static str> {
+        let bytes = self.bytes();
+        IMAGE_SIGNATURES
+            .iter()
+            .find(|(signature, _)| bytes.starts_with(signature))
+            .map(|(_, mime)| *mime)
+            .or_else(|| {
+                (bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP")
+                    .then_some("image/webp")
+            })
+    }
+
+    /// The MIME type of the contents, as far as it can be told from the bytes alone.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Contents::Text(_) => "text/plain;charset=utf-8",
+            Contents::Binary(_) => self.image_type().unwrap_or("application/octet-stream"),
+        }
+    }
+
+    /// A `data:` URL holding the contents.
+    pub fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.mime_type(),
+            STANDARD.encode(self.bytes())
+        )
+    }
+}
+
+impl Default for Contents {
+    fn default() -> Self {
+        Contents::Text("".into())
+    }
+}
+
+impl From<&str> for Contents {
+    fn from(text: &str) -> Self {
+        Contents::Text(text.into())
+    }
+}
+
+impl From<Rc<str>> for Contents {
+    fn from(text: Rc<str>) -> Self {
+        Contents::Text(text)
+    }
+}
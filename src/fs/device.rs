@@ -0,0 +1,99 @@
+use std::{cell::RefCell, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Contents, FsError, FsTree};
+
+/// How much a single read returns from devices with no end, such as `/dev/zero`.
+const READ_SIZE: usize = 256;
+
+thread_local! {
+    /// The last text known to be on the clipboard. The browser only hands out its clipboard
+    /// asynchronously and behind a permission prompt, so reads are served from here; writes and
+    /// the page's copy and paste events keep it current.
+    static CLIPBOARD: RefCell<Rc<str>> = RefCell::new("".into());
+}
+
+/// Records text that was copied or pasted on the page as the contents of `/dev/clipboard`.
+pub fn set_clipboard(text: &str) {
+    CLIPBOARD.with_borrow_mut(|clipboard| *clipboard = text.into());
+}
+
+#[cfg(target_arch = "wasm32")]
+fn random_bytes(len: usize) -> Vec<u8> {
+    use web_sys::js_sys::Math;
+
+    (0..len).map(|_| (Math::random() * 256.0) as u8).collect()
+}
+
+/// `Math.random` is unavailable off the web, so native builds draw on the randomly seeded
+/// hasher from the standard library instead.
+#[cfg(not(target_arch = "wasm32"))]
+fn random_bytes(len: usize) -> Vec<u8> {
+    use std::hash::{BuildHasher, RandomState};
+
+    let state = RandomState::new();
+    (0..len).map(|i| state.hash_one(i) as u8).collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_clipboard(text: &str) {
+    if let Some(window) = web_sys::window() {
+        // the write completes in the background; there is nowhere to report a failure to
+        let _ = window.navigator().clipboard().write_text(text);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_clipboard(_text: &str) {}
+
+/// Character devices, which produce or consume data rather than store it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Device {
+    /// Discards writes and reads as empty.
+    Null,
+    /// Reads as NUL bytes.
+    Zero,
+    /// Reads as random bytes.
+    Random,
+    /// Writes the browser clipboard. Reads return what was last copied, pasted or written on the
+    /// page rather than the system clipboard, which the browser only hands out asynchronously.
+    Clipboard,
+}
+
+impl Device {
+    pub(super) fn read(&self) -> Contents {
+        match self {
+            Device::Null => Contents::default(),
+            Device::Zero => Contents::Binary(vec![0; READ_SIZE].into()),
+            Device::Random => Contents::Binary(random_bytes(READ_SIZE).into()),
+            Device::Clipboard => Contents::Text(CLIPBOARD.with_borrow(Rc::clone)),
+        }
+    }
+
+    /// Only text can be put on the clipboard; every other device accepts anything.
+    pub(super) fn write(&self, contents: &Contents) -> Result<(), FsError> {
+        if let Device::Clipboard = self {
+            let text = contents.text()?;
+            set_clipboard(&text);
+            write_clipboard(&text);
+        }
+        Ok(())
+    }
+}
+
+/// Creates `/dev` and the devices in it.
+pub(super) fn mount(fs_tree: &mut FsTree) -> Result<(), FsError> {
+    let root = fs_tree.root();
+    let dev = fs_tree.create_directory("dev", root)?;
+    for (name, device) in [
+        ("null", Device::Null),
+        ("zero", Device::Zero),
+        ("random", Device::Random),
+        ("clipboard", Device::Clipboard),
+    ] {
+        fs_tree.create_device(name, dev, device)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+use unix_path::Path;
+
+use super::{Attributes, FsError, FsIndex, FsNode, FsTree, Inode, Quota};
+
+/// The visitor's changes on top of the site content, in the manner of the upper directory of
+/// an overlayfs mount: only entries that differ from the lower layer are recorded, entries
+/// removed from it are hidden by whiteouts, and directories that took the place of a lower one
+/// are marked opaque.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpperLayer {
+    /// Changes to the root directory, or `None` if nothing differs from the lower layer.
+    root: Option<UpperDirectory>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UpperDirectory {
+    /// Whether the directory replaces the lower directory of the same name instead of merging
+    /// with it, hiding everything the lower one holds.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    opaque: bool,
+    attributes: Attributes,
+    entries: BTreeMap<Rc<str>, UpperEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum UpperEntry {
+    /// Hides the lower entry of the same name.
+    Whiteout,
+    /// A directory, merged with the lower directory of the same name if there is one and it
+    /// isn't opaque.
+    Directory(UpperDirectory),
+    /// A file or symbolic link that replaces any lower entry of the same name.
+    Node(Inode),
+    /// Another name for the node at the given absolute path, replacing any lower entry of the
+    /// same name.
+    HardLink(Rc<str>),
+}
+
+/// Whether two inodes hold the same node with the same permissions. Timestamps are ignored:
+/// the lower layer is rebuilt on every visit, so its own timestamps never survive a reload.
+fn same_node(a: &Inode, b: &Inode) -> bool {
+    a.node == b.node
+        && a.attributes.mode == b.attributes.mode
+        && a.attributes.owner == b.attributes.owner
+}
+
+/// The entries of a directory other than `.` and `..`.
+fn children(fs_tree: &FsTree, dir: FsIndex) -> Result<HashMap<Rc<str>, FsIndex>, FsError> {
+    Ok(fs_tree
+        .iter_children(dir)?
+        .map(|entry| (entry.name(), entry.index()))
+        .collect())
+}
+
+impl UpperLayer {
+    /// Records how `merged` differs from `lower`.
+    pub fn between(lower: &FsTree, merged: &FsTree) -> Result<Self, FsError> {
+        Self::diff(lower, merged, true)
+    }
+
+    /// Records what `merged` adds to or changes in `lower`, leaving alone whatever it lacks. For
+    /// trees that were saved whole and so can't tell a removed entry from one `lower` gained
+    /// since.
+    pub fn without_whiteouts(lower: &FsTree, merged: &FsTree) -> Result<Self, FsError> {
+        Self::diff(lower, merged, false)
+    }
+
+    fn diff(lower: &FsTree, merged: &FsTree, whiteouts: bool) -> Result<Self, FsError> {
+        let mut diff = Diff {
+            lower,
+            merged,
+            whiteouts,
+            linked: HashMap::new(),
+        };
+        Ok(Self {
+            root: diff.directory(Some(lower.root()), merged.root(), "")?,
+        })
+    }
+
+    /// Number of entries in the layer other than whiteouts, including the directories leading
+    /// down to each change.
+    pub fn entries(&self) -> usize {
+        self.count(|entry| !matches!(entry, UpperEntry::Whiteout))
+    }
+
+    /// Number of lower entries hidden by whiteouts.
+    pub fn whiteouts(&self) -> usize {
+        self.count(|entry| matches!(entry, UpperEntry::Whiteout))
+    }
+
+    fn count(&self, predicate: impl Fn(&UpperEntry) -> bool) -> usize {
+        let mut count = 0;
+        let mut pending = Vec::from_iter(&self.root);
+        while let Some(dir) = pending.pop() {
+            for entry in dir.entries.values() {
+                if predicate(entry) {
+                    count += 1;
+                }
+                if let UpperEntry::Directory(subdir) = entry {
+                    pending.push(subdir);
+                }
+            }
+        }
+        count
+    }
+}
+
+struct Diff<'a> {
+    lower: &'a FsTree,
+    merged: &'a FsTree,
+    /// Whether lower entries missing from the merged tree are hidden by whiteouts.
+    whiteouts: bool,
+    /// The first path seen to each node with several names; the others are recorded as hard
+    /// links to it.
+    linked: HashMap<FsIndex, Rc<str>>,
+}
+
+impl Diff<'_> {
+    /// Diffs the directory `dir` of the merged tree, found at `path`, against `lower_dir`, returning
+    /// `None` if they match. A directory absent from the lower layer is always recorded, along with
+    /// all of its contents.
+    fn directory(
+        &mut self,
+        lower_dir: Option<FsIndex>,
+        dir: FsIndex,
+        path: &str,
+    ) -> Result<Option<UpperDirectory>, FsError> {
+        let (lower, merged) = (self.lower, self.merged);
+        let mut lower_children = match lower_dir {
+            Some(lower_dir) => children(lower, lower_dir)?,
+            None => HashMap::new(),
+        };
+
+        // visited in order, so that the same name of each node is always the one recorded in full
+        let mut merged_children = children(merged, dir)?.into_iter().collect::<Vec<_>>();
+        merged_children.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut entries = BTreeMap::new();
+        for (name, child) in merged_children {
+            let child_path = format!("{path}/{name}");
+            let inode = merged.get_inode(child)?;
+            let lower_child = lower_children.remove(&name);
+            if inode.links > 1 {
+                if let Some(first) = self.linked.get(&child) {
+                    entries.insert(name, UpperEntry::HardLink(Rc::clone(first)));
+                    continue;
+                }
+                self.linked.insert(child, child_path.as_str().into());
+            }
+            let lower_inode = lower_child
+                .map(|index| lower.get_inode(index))
+                .transpose()?;
+
+            let entry = match (&inode.node, lower_inode) {
+                (FsNode::Directory(_), Some(lower_inode)) => {
+                    // the merged tree starts as a copy of the lower one, so a directory in a
+                    // different slot was made in place of the lower directory rather than kept
+                    let lower_is_directory = matches!(lower_inode.node, FsNode::Directory(_));
+                    let replaced =
+                        lower_is_directory && self.whiteouts && lower_child != Some(child);
+                    let lower_child = lower_child.filter(|_| lower_is_directory && !replaced);
+                    self.directory(lower_child, child, &child_path)?.map(|dir| {
+                        UpperEntry::Directory(UpperDirectory {
+                            opaque: replaced,
+                            ..dir
+                        })
+                    })
+                }
+                (FsNode::Directory(_), None) => self
+                    .directory(None, child, &child_path)?
+                    .map(UpperEntry::Directory),
+                (_, Some(lower_inode)) if same_node(inode, lower_inode) => None,
+                _ => Some(UpperEntry::Node(inode.clone())),
+            };
+
+            if let Some(entry) = entry {
+                entries.insert(name, entry);
+            }
+        }
+
+        if self.whiteouts {
+            entries.extend(
+                lower_children
+                    .into_keys()
+                    .map(|name| (name, UpperEntry::Whiteout)),
+            );
+        }
+
+        let attributes = merged.get_inode(dir)?.attributes.clone();
+        let unchanged = match lower_dir {
+            Some(lower_dir) => {
+                let lower_attributes = &lower.get_inode(lower_dir)?.attributes;
+                lower_attributes.mode == attributes.mode
+                    && lower_attributes.owner == attributes.owner
+            }
+            None => false,
+        };
+
+        Ok(
+            (!unchanged || !entries.is_empty()).then_some(UpperDirectory {
+                opaque: false,
+                attributes,
+                entries,
+            }),
+        )
+    }
+}
+
+/// Applies the recorded changes to the directory `dir` of `fs_tree`. Hard links are left in
+/// `links` to be made once every node they could name exists.
+fn apply_directory(
+    fs_tree: &mut FsTree,
+    dir: FsIndex,
+    upper: &UpperDirectory,
+    links: &mut Vec<(FsIndex, Rc<str>, Rc<str>)>,
+) -> Result<(), FsError> {
+    for (name, entry) in &upper.entries {
+        let existing = match fs_tree.get_entry(name, dir) {
+            Ok(index) => Some(index),
+            Err(FsError::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+
+        match entry {
+            UpperEntry::Whiteout => {
+                if existing.is_some() {
+                    fs_tree.delete_recursive(name, dir)?;
+                }
+            }
+            UpperEntry::Directory(subdir) => {
+                let index = match existing {
+                    Some(index) if fs_tree.is_directory(index)? && !subdir.opaque => index,
+                    Some(_) => {
+                        fs_tree.delete_recursive(name, dir)?;
+                        fs_tree.create_directory(name, dir)?
+                    }
+                    None => fs_tree.create_directory(name, dir)?,
+                };
+                apply_directory(fs_tree, index, subdir, links)?;
+            }
+            UpperEntry::Node(inode) => {
+                // directories reference other slots, so they can only be stored as such
+                if matches!(inode.node, FsNode::Directory(_)) {
+                    return Err(FsError::IsADirectory);
+                }
+                if existing.is_some() {
+                    fs_tree.delete_recursive(name, dir)?;
+                }
+                let index = fs_tree.insert_node(name, dir, |_| inode.node.clone())?;
+                fs_tree.get_inode_mut(index)?.attributes = inode.attributes.clone();
+                fs_tree.reindex(index);
+            }
+            UpperEntry::HardLink(target) => {
+                if existing.is_some() {
+                    fs_tree.delete_recursive(name, dir)?;
+                }
+                links.push((dir, Rc::clone(name), Rc::clone(target)));
+            }
+        }
+    }
+
+    fs_tree.get_inode_mut(dir)?.attributes = upper.attributes.clone();
+    Ok(())
+}
+
+impl FsTree {
+    /// Mounts `upper` over a read-only `lower` tree, returning the merged tree. Programs only
+    /// ever modify the merged tree; the lower layer stays untouched and can be recovered with
+    /// [`FsTree::discard_upper`]. Quotas aren't enforced while the layer is applied, so changes
+    /// saved under a larger quota still load, though they can't grow any further.
+    pub fn overlay(lower: Rc<FsTree>, upper: &UpperLayer) -> Result<FsTree, FsError> {
+        let mut fs_tree = FsTree::clone(&lower);
+        fs_tree.lower = Some(lower);
+        let quota = fs_tree.quota;
+        fs_tree.quota = Quota::UNLIMITED;
+
+        if let Some(root) = &upper.root {
+            let root_index = fs_tree.root();
+            let mut links = Vec::new();
+            apply_directory(&mut fs_tree, root_index, root, &mut links)?;
+            for (dir, name, target) in links {
+                let (target, _) = fs_tree.resolve_nofollow(Path::new(&*target), Path::new("/"))?;
+                fs_tree.create_hard_link(&name, dir, target)?;
+            }
+        }
+
+        fs_tree.quota = quota;
+        Ok(fs_tree)
+    }
+
+    /// The read-only layer this tree was mounted over, if any.
+    pub fn lower(&self) -> Option<&FsTree> {
+        self.lower.as_deref()
+    }
+
+    /// The changes made on top of the lower layer. Without a lower layer, everything is a change.
+    pub fn upper_layer(&self) -> Result<UpperLayer, FsError> {
+        match &self.lower {
+            Some(lower) => UpperLayer::between(lower, self),
+            None => UpperLayer::between(&FsTree::new(), self),
+        }
+    }
+
+    /// Throws away every change made on top of the lower layer.
+    pub fn discard_upper(&mut self) {
+        let revision = self.revision + 1;
+        let quota = self.quota;
+        *self = match self.lower.take() {
+            Some(lower) => FsTree {
+                lower: Some(Rc::clone(&lower)),
+                ..FsTree::clone(&lower)
+            },
+            None => FsTree::new(),
+        };
+        self.revision = revision;
+        self.quota = quota;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use unix_path::Path;
+
+    use super::UpperLayer;
+    use crate::fs::{FsError, FsTree};
+
+    /// `/docs/old.txt` and `/keep/kept.txt`, along with `extra` in both directories.
+    fn site(extra: &[&str]) -> Rc<FsTree> {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        for (dir, name) in [("docs", "old.txt"), ("keep", "kept.txt")] {
+            let dir = fs_tree.create_directory(dir, root).unwrap();
+            for name in extra.iter().chain([&name]) {
+                fs_tree.create_file(name, dir).unwrap();
+            }
+        }
+        Rc::new(fs_tree)
+    }
+
+    #[test]
+    fn recreated_directory_hides_lower_contents() {
+        let lower = site(&[]);
+        let mut merged = FsTree::overlay(Rc::clone(&lower), &UpperLayer::default()).unwrap();
+        let root = merged.root();
+        merged.delete_recursive("docs", root).unwrap();
+        let docs = merged.create_directory("docs", root).unwrap();
+        merged.create_file("new.txt", docs).unwrap();
+        let keep = merged.lookup_path(Path::new("/keep")).unwrap();
+        merged.create_file("added.txt", keep).unwrap();
+
+        let upper = merged.upper_layer().unwrap();
+        assert_eq!(upper.whiteouts(), 0);
+
+        // content deployed since shows up in kept directories, but not in replaced ones
+        let redeployed = site(&["later.txt"]);
+        let reloaded = FsTree::overlay(Rc::clone(&redeployed), &upper).unwrap();
+        let exists = |path: &str| match reloaded.lookup_path(Path::new(path)) {
+            Ok(_) => true,
+            Err(FsError::NotFound) => false,
+            Err(error) => panic!("{path}: {error}"),
+        };
+        assert!(!exists("/docs/old.txt"));
+        assert!(!exists("/docs/later.txt"));
+        assert!(exists("/docs/new.txt"));
+        assert!(exists("/keep/kept.txt"));
+        assert!(exists("/keep/later.txt"));
+        assert!(exists("/keep/added.txt"));
+
+        // the replacement stays opaque through further saves
+        assert_eq!(reloaded.upper_layer().unwrap(), upper);
+        assert_eq!(reloaded.check(), []);
+    }
+
+    #[test]
+    fn untouched_directories_leave_no_trace() {
+        let lower = site(&[]);
+        let merged = FsTree::overlay(Rc::clone(&lower), &UpperLayer::default()).unwrap();
+        assert_eq!(merged.upper_layer().unwrap(), UpperLayer::default());
+    }
+}
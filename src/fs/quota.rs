@@ -0,0 +1,341 @@
+//! Limits on how much a tree may hold, so that a runaway script can't grow it without bound and
+//! overflow the storage it is saved to.
+
+use super::{FsError, FsIndex, FsNode, FsTree};
+
+/// The most a tree may hold. Creating a node or writing a file that would go past any of these
+/// fails with [`FsError::NoSpace`]. Visitors can't change the limits; every tree gets the
+/// [`Default`] ones.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quota {
+    /// Nodes of any kind, including directories, links and devices.
+    pub nodes: usize,
+    /// Bytes held by all files together.
+    pub bytes: usize,
+    /// Bytes held by any one file.
+    pub file_size: usize,
+    /// How far below the root a node may lie; `/home` is at depth 1.
+    pub depth: usize,
+}
+
+impl Default for Quota {
+    /// Sized so that the site content and a visitor's changes fit comfortably within the few
+    /// megabytes browsers give localStorage.
+    fn default() -> Self {
+        Self {
+            nodes: 10_000,
+            bytes: 2 * 1024 * 1024,
+            file_size: 512 * 1024,
+            depth: 32,
+        }
+    }
+}
+
+impl Quota {
+    /// No limits at all, for restoring what was already saved.
+    pub(super) const UNLIMITED: Quota = Quota {
+        nodes: usize::MAX,
+        bytes: usize::MAX,
+        file_size: usize::MAX,
+        depth: usize::MAX,
+    };
+}
+
+/// How much of each [`Quota`] a tree uses.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Usage {
+    pub nodes: usize,
+    pub bytes: usize,
+    /// Size of the largest file.
+    pub file_size: usize,
+    /// Depth of the deepest node.
+    pub depth: usize,
+}
+
+impl Usage {
+    /// Whether anything is past its limit, as a tree restored under a smaller quota can be.
+    pub fn exceeds(&self, quota: &Quota) -> bool {
+        self.nodes > quota.nodes
+            || self.bytes > quota.bytes
+            || self.file_size > quota.file_size
+            || self.depth > quota.depth
+    }
+}
+
+/// The running totals kept as nodes are created, written and removed, so that quotas can be
+/// checked without walking the tree.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(super) struct Totals {
+    pub(super) nodes: usize,
+    pub(super) bytes: usize,
+}
+
+impl FsNode {
+    /// Bytes the node counts against [`Quota::bytes`].
+    pub(super) fn stored_bytes(&self) -> usize {
+        match self {
+            FsNode::File(file) => file.contents.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl FsTree {
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Measures the tree against its quota. The largest file and deepest node are found by
+    /// walking everything reachable from the root.
+    pub fn usage(&self) -> Usage {
+        let mut usage = Usage {
+            nodes: self.totals.nodes,
+            bytes: self.totals.bytes,
+            ..Usage::default()
+        };
+        let mut pending = vec![(self.root(), 0)];
+        while let Some((index, depth)) = pending.pop() {
+            usage.depth = usage.depth.max(depth);
+            match self.get_node(index) {
+                Ok(FsNode::Directory(_)) => pending.extend(
+                    self.iter_children(index)
+                        .into_iter()
+                        .flatten()
+                        .map(|entry| (entry.index(), depth + 1)),
+                ),
+                Ok(node) => usage.file_size = usage.file_size.max(node.stored_bytes()),
+                Err(_) => {}
+            }
+        }
+        usage
+    }
+
+    /// How far below the root `index` lies.
+    pub(super) fn depth(&self, index: FsIndex) -> Result<usize, FsError> {
+        let mut depth = 0;
+        let mut current = index;
+        while current != self.root() {
+            current = self.get_entry("..", current)?;
+            depth += 1;
+        }
+        Ok(depth)
+    }
+
+    /// How far the deepest node beneath `index` lies below it.
+    pub(super) fn height(&self, index: FsIndex) -> usize {
+        let mut height = 0;
+        let mut pending = vec![(index, 0)];
+        while let Some((index, depth)) = pending.pop() {
+            height = height.max(depth);
+            if let Ok(children) = self.iter_children(index) {
+                pending.extend(children.map(|entry| (entry.index(), depth + 1)));
+            }
+        }
+        height
+    }
+
+    /// Checks that another node can be created in `parent`.
+    pub(super) fn check_node_quota(&self, parent: FsIndex) -> Result<(), FsError> {
+        if self.totals.nodes >= self.quota.nodes {
+            return Err(FsError::NoSpace);
+        }
+        self.check_depth_quota(parent)
+    }
+
+    /// Checks that another name can be added to `parent`.
+    pub(super) fn check_depth_quota(&self, parent: FsIndex) -> Result<(), FsError> {
+        if self.depth(parent)? >= self.quota.depth {
+            return Err(FsError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// Checks that a file holding `old_size` bytes can be rewritten to hold `new_size`.
+    pub(super) fn check_write_quota(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<(), FsError> {
+        if new_size > old_size
+            && (new_size > self.quota.file_size
+                || self.totals.bytes - old_size + new_size > self.quota.bytes)
+        {
+            return Err(FsError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// Checks that `source` can move from `old_parent` to `new_parent` without anything beneath
+    /// it ending up too deep.
+    pub(super) fn check_move_quota(
+        &self,
+        source: FsIndex,
+        old_parent: FsIndex,
+        new_parent: FsIndex,
+    ) -> Result<(), FsError> {
+        let depth = self.depth(new_parent)?;
+        if depth > self.depth(old_parent)? && depth + 1 + self.height(source) > self.quota.depth {
+            return Err(FsError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// Counts the totals afresh from the node table.
+    pub(super) fn recount(&mut self) {
+        self.totals = self
+            .node_table
+            .iter()
+            .filter_map(|slot| slot.inode.as_ref())
+            .fold(Totals::default(), |totals, inode| Totals {
+                nodes: totals.nodes + 1,
+                bytes: totals.bytes + inode.node.stored_bytes(),
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Quota, Usage};
+    use crate::fs::{FsError, FsTree, UpperLayer};
+
+    const SMALL: Quota = Quota {
+        nodes: 4,
+        bytes: 8,
+        file_size: 6,
+        depth: 2,
+    };
+
+    /// An empty tree held to `quota`.
+    fn limited(quota: Quota) -> FsTree {
+        let mut fs_tree = FsTree::new();
+        fs_tree.quota = quota;
+        fs_tree
+    }
+
+    /// A tree under no particular quota holding more than [`SMALL`] allows.
+    fn large_upper() -> UpperLayer {
+        let mut fs_tree = FsTree::overlay(Rc::new(FsTree::new()), &UpperLayer::default()).unwrap();
+        let root = fs_tree.root();
+        let deep = fs_tree.create_directory("a", root).unwrap();
+        let deep = fs_tree.create_directory("b", deep).unwrap();
+        let deep = fs_tree.create_directory("c", deep).unwrap();
+        let file = fs_tree.create_file("big", deep).unwrap();
+        fs_tree.write(file, "0123456789").unwrap();
+        fs_tree.upper_layer().unwrap()
+    }
+
+    #[test]
+    fn over_quota_layer_survives_overlay() {
+        let mut fs_tree = FsTree::overlay(Rc::new(limited(SMALL)), &large_upper()).unwrap();
+
+        assert_eq!(fs_tree.quota(), SMALL);
+        assert_eq!(
+            fs_tree.usage(),
+            Usage {
+                nodes: 5,
+                bytes: 10,
+                file_size: 10,
+                depth: 4,
+            }
+        );
+        assert!(fs_tree.usage().exceeds(&SMALL));
+        assert!(fs_tree.check().is_empty());
+
+        // nothing more fits, but what is there can still shrink
+        let root = fs_tree.root();
+        assert_eq!(fs_tree.create_file("more", root), Err(FsError::NoSpace));
+        let (big, _) = fs_tree
+            .resolve("/a/b/c/big".as_ref(), "/".as_ref())
+            .unwrap();
+        assert_eq!(fs_tree.write(big, "0123456789ab"), Err(FsError::NoSpace));
+        fs_tree.write(big, "0123").unwrap();
+        assert_eq!(fs_tree.usage().bytes, 4);
+
+        // and it comes through another round trip unchanged
+        let upper = fs_tree.upper_layer().unwrap();
+        let reloaded = FsTree::overlay(Rc::new(fs_tree.lower().unwrap().clone()), &upper).unwrap();
+        assert_eq!(reloaded.usage(), fs_tree.usage());
+    }
+
+    #[test]
+    fn limits_apply_to_new_nodes_and_writes() {
+        let mut fs_tree = limited(SMALL);
+        let root = fs_tree.root();
+        let dir = fs_tree.create_directory("a", root).unwrap();
+        let dir = fs_tree.create_directory("b", dir).unwrap();
+        assert_eq!(fs_tree.create_file("deep", dir), Err(FsError::NoSpace));
+
+        let file = fs_tree.create_file("f", root).unwrap();
+        assert_eq!(fs_tree.write(file, "1234567"), Err(FsError::NoSpace));
+        fs_tree.write(file, "123456").unwrap();
+        assert_eq!(fs_tree.create_file("g", root), Err(FsError::NoSpace));
+    }
+
+    #[test]
+    fn node_limit_covers_every_kind_of_node() {
+        let mut fs_tree = limited(Quota {
+            nodes: 3,
+            ..Quota::default()
+        });
+        let root = fs_tree.root();
+        let dir = fs_tree.create_directory("dir", root).unwrap();
+        let file = fs_tree.create_file("file", root).unwrap();
+
+        assert_eq!(fs_tree.create_file("more", dir), Err(FsError::NoSpace));
+        assert_eq!(fs_tree.create_directory("more", dir), Err(FsError::NoSpace));
+        assert_eq!(
+            fs_tree.create_symlink("more", dir, "/file"),
+            Err(FsError::NoSpace)
+        );
+        // another name for an existing file is not another node
+        fs_tree.create_hard_link("link", dir, file).unwrap();
+
+        fs_tree.delete("file", root).unwrap();
+        fs_tree.delete("link", dir).unwrap();
+        fs_tree.create_symlink("more", dir, "/file").unwrap();
+        assert!(fs_tree.check().is_empty());
+    }
+
+    #[test]
+    fn byte_limit_covers_all_files_together() {
+        let mut fs_tree = limited(SMALL);
+        let root = fs_tree.root();
+        let first = fs_tree.create_file("first", root).unwrap();
+        let second = fs_tree.create_file("second", root).unwrap();
+        fs_tree.write(first, "123456").unwrap();
+
+        // each write fits in a file, but not alongside the other
+        assert_eq!(fs_tree.write(second, "123"), Err(FsError::NoSpace));
+        assert_eq!(fs_tree.read_text(second).unwrap().as_ref(), "");
+        fs_tree.write(second, "12").unwrap();
+        assert_eq!(fs_tree.usage().bytes, 8);
+
+        // rewriting a file only counts the difference
+        fs_tree.write(first, "12345").unwrap();
+        fs_tree.write(second, "123").unwrap();
+        assert_eq!(fs_tree.usage().bytes, 8);
+    }
+
+    #[test]
+    fn depth_limit_covers_moves() {
+        let mut fs_tree = limited(SMALL);
+        let root = fs_tree.root();
+        let a = fs_tree.create_directory("a", root).unwrap();
+        let x = fs_tree.create_directory("x", root).unwrap();
+        fs_tree.create_directory("y", x).unwrap();
+
+        // `/a/x/y` would be three levels down
+        assert_eq!(fs_tree.move_entry("x", root, "x", a), Err(FsError::NoSpace));
+        assert_eq!(fs_tree.get_entry("x", root), Ok(x));
+        assert_eq!(fs_tree.get_entry("x", a), Err(FsError::NotFound));
+
+        // moving up is always allowed, and then the rest fits
+        fs_tree.move_entry("y", x, "y", root).unwrap();
+        fs_tree.move_entry("x", root, "x", a).unwrap();
+        let a_x = fs_tree.get_entry("x", a).unwrap();
+        assert_eq!(fs_tree.create_file("z", a_x), Err(FsError::NoSpace));
+        assert!(fs_tree.check().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! A full-text index over the text files in a tree, kept up to date as files are written and
+//! removed, and ranked with BM25.
+
+use std::{collections::HashMap as StdHashMap, ops::Range, rc::Rc};
+
+use im_rc::HashMap;
+
+use super::FsIndex;
+
+/// How quickly repeated occurrences of a term stop adding to a document's score.
+const K1: f64 = 1.2;
+/// How strongly scores are normalized by document length.
+const B: f64 = 0.75;
+
+/// Strips common English inflections so that related forms of a word index the same: `notes`,
+/// `noted` and `noting` all become `note`. This is a light take on the first step of Porter's
+/// algorithm rather than a full stemmer.
+pub fn stem(word: &str) -> String {
+    let word = word.to_lowercase();
+    let has_vowel = |stem: &str| stem.contains(['a', 'e', 'i', 'o', 'u', 'y']);
+
+    if let Some(stem) = word.strip_suffix("sses") {
+        return format!("{stem}ss");
+    }
+    if let Some(stem) = word.strip_suffix("ies")
+        && stem.len() > 1
+    {
+        return format!("{stem}y");
+    }
+    if word.ends_with("ss") || word.ends_with("us") || word.ends_with("is") {
+        return word;
+    }
+    if let Some(stem) = word.strip_suffix('s')
+        && stem.len() > 2
+    {
+        return stem.into();
+    }
+
+    for suffix in ["ing", "ed"] {
+        let Some(stem) = word.strip_suffix(suffix) else {
+            continue;
+        };
+        let length = stem.chars().count();
+        if length < 3 || !has_vowel(stem) {
+            return word;
+        }
+        // undo the doubled consonant of `running` (but not of `falling`), and restore the `e`
+        // dropped from `hoping` and `noted`
+        let vowel = |c: char| "aeiouy".contains(c);
+        let chars = stem.chars().rev().take(3).collect::<Vec<_>>();
+        return match chars[..] {
+            [last, before, ..] if last == before && !vowel(last) && !"lsz".contains(last) => {
+                let mut stem = stem.to_owned();
+                stem.pop();
+                stem
+            }
+            _ if stem.ends_with("at") || stem.ends_with("iz") || stem.ends_with("bl") => {
+                format!("{stem}e")
+            }
+            [last, middle, first]
+                if length == 3 && !vowel(first) && vowel(middle) && !"aeiouwxy".contains(last) =>
+            {
+                format!("{stem}e")
+            }
+            _ => stem.into(),
+        };
+    }
+
+    word
+}
+
+/// Splits `text` into words, yielding where each one lies along with its stem.
+pub fn tokens(text: &str) -> impl Iterator<Item = (Range<usize>, String)> {
+    let mut start = None;
+    text.char_indices()
+        .chain([(text.len(), ' ')])
+        .filter_map(move |(i, c)| match (c.is_alphanumeric(), start) {
+            (true, None) => {
+                start = Some(i);
+                None
+            }
+            (false, Some(word_start)) => {
+                start = None;
+                Some((word_start..i, stem(&text[word_start..i])))
+            }
+            _ => None,
+        })
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub(super) struct SearchIndex {
+    /// For every term, how often it occurs in each document containing it.
+    postings: HashMap<Rc<str>, HashMap<FsIndex, u32>>,
+    /// The terms of every indexed document, so they can be found again when it changes, and
+    /// its length in words.
+    documents: HashMap<FsIndex, (Vec<Rc<str>>, u32)>,
+    /// The length of all documents together, for the average BM25 normalizes by.
+    total_length: u64,
+}
+
+impl SearchIndex {
+    /// Indexes `text` as the contents of `document`, replacing whatever it held before.
+    pub(super) fn update(&mut self, document: FsIndex, text: &str) {
+        self.remove(document);
+
+        let mut frequencies = StdHashMap::<Rc<str>, u32>::new();
+        let mut length = 0;
+        for (_, term) in tokens(text) {
+            *frequencies.entry(term.into()).or_default() += 1;
+            length += 1;
+        }
+
+        let terms = frequencies
+            .into_iter()
+            .map(|(term, frequency)| {
+                self.postings
+                    .entry(Rc::clone(&term))
+                    .or_default()
+                    .insert(document, frequency);
+                term
+            })
+            .collect();
+        self.documents.insert(document, (terms, length));
+        self.total_length += u64::from(length);
+    }
+
+    pub(super) fn remove(&mut self, document: FsIndex) {
+        let Some((terms, length)) = self.documents.remove(&document) else {
+            return;
+        };
+        for term in terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&document);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_length -= u64::from(length);
+    }
+
+    /// Scores every document containing any of `terms`, best first.
+    pub(super) fn search(&self, terms: &[String]) -> Vec<(FsIndex, f64)> {
+        if self.documents.is_empty() {
+            return Vec::new();
+        }
+        let document_count = self.documents.len() as f64;
+        let average_length = self.total_length as f64 / document_count;
+
+        let mut scores = StdHashMap::<FsIndex, f64>::new();
+        for posting in terms
+            .iter()
+            .filter_map(|term| self.postings.get(term.as_str()))
+        {
+            let matching = posting.len() as f64;
+            let idf = ((document_count - matching + 0.5) / (matching + 0.5) + 1.0).ln();
+            for (document, &frequency) in posting {
+                let frequency = f64::from(frequency);
+                let length = f64::from(self.documents[document].1);
+                let normalization = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(*document).or_default() +=
+                    idf * frequency * (K1 + 1.0) / (frequency + normalization);
+            }
+        }
+
+        let mut ranked = scores.into_iter().collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stem, tokens};
+
+    #[test]
+    fn stems_inflections() {
+        for (word, expected) in [
+            ("notes", "note"),
+            ("noted", "note"),
+            ("noting", "note"),
+            ("running", "run"),
+            ("falling", "fall"),
+            ("hoping", "hope"),
+            ("classes", "class"),
+            ("ponies", "pony"),
+            ("Status", "status"),
+            ("sing", "sing"),
+        ] {
+            assert_eq!(stem(word), expected, "stem of {word}");
+        }
+    }
+
+    #[test]
+    fn stems_non_ascii_words() {
+        assert_eq!(stem("straßßing"), "straß");
+        assert_eq!(stem("ÉTÉS"), "été");
+        assert_eq!(stem("über"), "über");
+        assert_eq!(stem("ßing"), "ßing");
+    }
+
+    #[test]
+    fn splits_words_with_their_positions() {
+        let text = "Grüße, straßßing — naïve café2!";
+        let words = tokens(text)
+            .map(|(range, term)| (&text[range], term))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            words,
+            [
+                ("Grüße", "grüße".to_owned()),
+                ("straßßing", "straß".to_owned()),
+                ("naïve", "naïve".to_owned()),
+                ("café2", "café2".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_nothing_from_punctuation() {
+        assert_eq!(tokens("  -- ... !").count(), 0);
+        assert_eq!(tokens("").count(), 0);
+    }
+}
@@ -0,0 +1,46 @@
+//! Copies of a whole tree taken so that it can be put back later. The node table is persistent,
+//! so a snapshot shares every node with the live tree and costs little until one of them changes.
+
+use std::rc::Rc;
+
+use super::{FsTree, now};
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    tree: FsTree,
+    taken: u64,
+}
+
+impl Snapshot {
+    /// When the snapshot was taken, in milliseconds since the epoch.
+    pub fn taken(&self) -> u64 {
+        self.taken
+    }
+
+    /// Number of nodes in the snapshot.
+    pub fn node_count(&self) -> usize {
+        self.tree.node_count()
+    }
+}
+
+impl FsTree {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            tree: self.clone(),
+            taken: now(),
+        }
+    }
+
+    /// Puts the tree back as it was when `snapshot` was taken. The lower layer and quota are
+    /// kept, so a restored overlay still saves only what differs from it.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let revision = self.revision + 1;
+        let lower = self.lower.as_ref().map(Rc::clone);
+        *self = FsTree {
+            lower,
+            quota: self.quota,
+            ..snapshot.tree.clone()
+        };
+        self.revision = revision;
+    }
+}
@@ -0,0 +1,346 @@
+//! Pathname expansion. Patterns use `*`, `?`, `[abc]`, `[!a-z]` and `**`, and a backslash makes
+//! the following character literal.
+
+use unix_path::Path;
+
+use crate::fs::FsTree;
+
+/// Characters with a special meaning in patterns.
+const METACHARACTERS: &[char] = &['*', '?', '['];
+
+/// Whether `pattern` contains any unescaped metacharacter.
+pub fn is_pattern(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if METACHARACTERS.contains(&c) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the backslashes escaping characters in `pattern`.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+enum Token {
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyString,
+    /// `[...]`, as ranges of characters.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '?' => tokens.push(Token::AnyChar),
+            '*' => tokens.push(Token::AnyString),
+            '[' => {
+                if let Some((class, end)) = parse_class(&chars, i) {
+                    tokens.push(class);
+                    i = end;
+                    continue;
+                }
+                // an unterminated `[` stands for itself
+                tokens.push(Token::Literal('['));
+            }
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the bracket expression opening at `chars[start]`, returning it and the index just past
+/// its closing `]`.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                ranges.push((c, end));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Whether `name` matches `pattern` in full. Unlike pathname expansion, a leading `.` needs no
+/// special treatment here.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let tokens = tokenize(pattern);
+    let name = name.chars().collect::<Vec<_>>();
+
+    // position to resume from after the last `*`, and how much of the name it has consumed
+    let mut backtrack = None;
+    let (mut t, mut n) = (0, 0);
+    while n < name.len() {
+        let matched = match tokens.get(t) {
+            Some(Token::AnyString) => {
+                backtrack = Some((t, n));
+                t += 1;
+                continue;
+            }
+            Some(Token::Literal(c)) => *c == name[n],
+            Some(Token::AnyChar) => true,
+            Some(Token::Class { negated, ranges }) => {
+                ranges
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&name[n]))
+                    != *negated
+            }
+            None => false,
+        };
+
+        if matched {
+            t += 1;
+            n += 1;
+        } else if let Some((star, consumed)) = backtrack {
+            t = star + 1;
+            n = consumed + 1;
+            backtrack = Some((star, consumed + 1));
+        } else {
+            return false;
+        }
+    }
+
+    tokens[t..]
+        .iter()
+        .all(|token| matches!(token, Token::AnyString))
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    match prefix {
+        "" => name.into(),
+        prefix if prefix.ends_with('/') => format!("{prefix}{name}"),
+        prefix => format!("{prefix}/{name}"),
+    }
+}
+
+/// Expands `pattern` to the sorted paths it matches, relative to `cwd` unless the pattern is
+/// absolute. Wildcards don't match a leading `.` unless the pattern component starts with one,
+/// and `**` matches any number of directories. Returns nothing if no path matches.
+pub fn expand(pattern: &str, cwd: &Path, fs_tree: &FsTree) -> Vec<String> {
+    let prefix = if pattern.starts_with('/') { "/" } else { "" };
+    let components = pattern
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    walk(
+        fs_tree,
+        cwd,
+        prefix,
+        &components,
+        pattern.ends_with('/'),
+        &mut found,
+    );
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn walk(
+    fs_tree: &FsTree,
+    cwd: &Path,
+    prefix: &str,
+    components: &[&str],
+    directories_only: bool,
+    found: &mut Vec<String>,
+) {
+    let Some((component, rest)) = components.split_first() else {
+        if directories_only {
+            if let Ok((index, _)) = fs_tree.resolve(Path::new(prefix), cwd)
+                && fs_tree.is_directory(index).unwrap_or(false)
+            {
+                found.push(join(prefix, ""));
+            }
+        } else if fs_tree.resolve_nofollow(Path::new(prefix), cwd).is_ok() {
+            found.push(prefix.into());
+        }
+        return;
+    };
+
+    if !is_pattern(component) {
+        let path = join(prefix, &unescape(component));
+        walk(fs_tree, cwd, &path, rest, directories_only, found);
+        return;
+    }
+
+    let directory = if prefix.is_empty() { "." } else { prefix };
+    let Ok((dir, _)) = fs_tree.resolve(Path::new(directory), cwd) else {
+        return;
+    };
+    let Ok(entries) = fs_tree.iter_children(dir) else {
+        return;
+    };
+
+    for entry in entries {
+        let name = entry.name();
+        // wildcards never match a leading `.`; only a pattern that spells it out does
+        if name.starts_with('.') && (*component == "**" || !component.starts_with('.')) {
+            continue;
+        }
+
+        let path = join(prefix, &name);
+        if *component == "**" {
+            // descend without following symbolic links, keeping `**` for the next level
+            if fs_tree.is_directory(entry.index()).unwrap_or(false) {
+                walk(fs_tree, cwd, &path, components, directories_only, found);
+            }
+        } else if matches(component, &name) {
+            walk(fs_tree, cwd, &path, rest, directories_only, found);
+        }
+    }
+
+    // `**` also matches no directories at all
+    if *component == "**" {
+        walk(fs_tree, cwd, prefix, rest, directories_only, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unix_path::Path;
+
+    use super::{expand, matches};
+    use crate::fs::FsTree;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a*b*c", "abbbc"));
+        assert!(matches("n?tes", "notes"));
+        assert!(!matches("n?tes", "ntes"));
+        assert!(matches("*", ""));
+        assert!(matches("grüß?", "grüße"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(!matches("[abc]x", "dx"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[!0-9]", "file7"));
+        assert!(matches("file[^0-9]", "filea"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        // an unterminated class stands for itself
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn matches_escapes_literally() {
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "a"));
+        assert!(matches(r"what\?", "what?"));
+        assert!(!matches(r"what\?", "whats"));
+        assert!(matches(r"[\]]", "]"));
+    }
+
+    fn tree() -> FsTree {
+        let mut fs_tree = FsTree::new();
+        let root = fs_tree.root();
+        let docs = fs_tree.create_directory("docs", root).unwrap();
+        for name in ["a.txt", "b.txt", "c.md", ".hidden.txt", "*.log"] {
+            fs_tree.create_file(name, docs).unwrap();
+        }
+        let deep = fs_tree.create_directory("deep", docs).unwrap();
+        fs_tree.create_file("d.txt", deep).unwrap();
+        fs_tree
+    }
+
+    #[test]
+    fn expands_to_sorted_paths() {
+        let fs_tree = tree();
+        let cwd = Path::new("/docs");
+        assert_eq!(expand("?.txt", cwd, &fs_tree), ["a.txt", "b.txt"]);
+        assert_eq!(expand("[ac].*", cwd, &fs_tree), ["a.txt", "c.md"]);
+        assert_eq!(
+            expand("/docs/*.md", Path::new("/"), &fs_tree),
+            ["/docs/c.md"]
+        );
+        assert_eq!(expand("*/", cwd, &fs_tree), ["deep/"]);
+        assert_eq!(
+            expand("**/*.txt", Path::new("/"), &fs_tree),
+            ["docs/a.txt", "docs/b.txt", "docs/deep/d.txt"]
+        );
+    }
+
+    #[test]
+    fn expands_dotfiles_only_when_spelled_out() {
+        let fs_tree = tree();
+        let cwd = Path::new("/docs");
+        assert!(!expand("*", cwd, &fs_tree).contains(&".hidden.txt".into()));
+        assert_eq!(expand(".*.txt", cwd, &fs_tree), [".hidden.txt"]);
+    }
+
+    #[test]
+    fn expands_escapes_literally() {
+        let fs_tree = tree();
+        let cwd = Path::new("/docs");
+        // a quoted `*` reaches expansion escaped, and only names itself
+        assert_eq!(expand(r"\*.log", cwd, &fs_tree), ["*.log"]);
+        assert_eq!(expand(r"\*.txt", cwd, &fs_tree), Vec::<String>::new());
+        assert_eq!(expand("*.log", cwd, &fs_tree), ["*.log"]);
+    }
+
+    #[test]
+    fn expands_to_nothing_without_a_match() {
+        let fs_tree = tree();
+        let cwd = Path::new("/docs");
+        assert!(expand("*.rs", cwd, &fs_tree).is_empty());
+        assert!(expand("missing/*", cwd, &fs_tree).is_empty());
+    }
+}
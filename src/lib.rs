@@ -1,20 +1,34 @@
 #![feature(binary_heap_into_iter_sorted)]
-use std::{cell::RefCell, error::Error, ffi::OsStr, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 use programs::EXECUTE_FILE;
+use thiserror::Error;
 use unix_path::{Path, PathBuf};
-use unix_str::UnixStr;
 use web_sys::console;
-use yew::{html, Html, UseStateHandle};
+use yew::{Html, UseStateHandle, html, virtual_dom::VNode};
 
 use crate::{
-    fs::{FsError, FsIndex, FsTree},
+    fs::{FsError, FsIndex, FsTree, UpperLayer},
+    parse::Word,
+    persist::PersistError,
     programs::{PROGRAMS, Program},
 };
 
 pub mod components;
+pub mod content;
+pub mod env;
 pub mod fs;
+pub mod glob;
+pub mod parse;
+pub mod persist;
+pub mod proc;
 pub mod programs;
+pub mod tar;
+pub mod transfer;
 
 pub const HOME: &str = "/home/user";
 
@@ -22,17 +36,27 @@ pub const HOME: &str = "/home/user";
 pub struct StatusCode(pub u32);
 
 impl StatusCode {
-    fn is_success(&self) -> bool {
+    pub fn is_success(&self) -> bool {
         self.0 == 0
     }
 
-    fn is_failure(&self) -> bool {
+    pub fn is_failure(&self) -> bool {
         !self.is_success()
     }
 }
 
+/// Identifies an execution record for as long as it stays in the history, however many records
+/// are added or cleared around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RecordId(u64);
+
+thread_local! {
+    static NEXT_RECORD_ID: Cell<u64> = const { Cell::new(0) };
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExecutionRecord {
+    id: RecordId,
     last_status: StatusCode,
     cwd_display: Rc<str>,
     command: Rc<str>,
@@ -47,6 +71,7 @@ impl ExecutionRecord {
         output: Option<Html>,
     ) -> Self {
         Self {
+            id: RecordId(NEXT_RECORD_ID.replace(NEXT_RECORD_ID.get() + 1)),
             last_status,
             cwd_display: cwd_display.into(),
             command: command.into(),
@@ -54,6 +79,10 @@ impl ExecutionRecord {
         }
     }
 
+    pub fn id(&self) -> RecordId {
+        self.id
+    }
+
     pub fn last_status(&self) -> StatusCode {
         self.last_status
     }
@@ -73,6 +102,10 @@ impl ExecutionRecord {
     // TODO write function here?
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("no execution record to write to")]
+pub struct EmptyHistoryError;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct History(pub Vec<ExecutionRecord>);
 
@@ -81,10 +114,26 @@ impl History {
         self.0.clear()
     }
 
-    pub fn write(&mut self, output: Html) -> Result<(), ()> {
-        self.0.last_mut().ok_or(())?.output = output.into();
+    pub fn write(&mut self, output: Html) -> Result<(), EmptyHistoryError> {
+        self.0.last_mut().ok_or(EmptyHistoryError)?.output = output.into();
         Ok(())
     }
+
+    /// Adds `output` below whatever the record `record` already shows, for results that arrive
+    /// after their command has finished. Nothing is shown if the record has since been cleared.
+    pub fn append_output(&mut self, record: RecordId, output: Html) {
+        if let Some(record) = self.0.iter_mut().find(|existing| existing.id == record) {
+            record.output = Some(match record.output.take() {
+                Some(existing) => html! {<>{existing}<br />{output}</>},
+                None => output,
+            });
+        }
+    }
+
+    /// Removes the output of the last record, e.g. to send it somewhere other than the screen.
+    pub fn take_output(&mut self) -> Option<Html> {
+        self.0.last_mut()?.output.take()
+    }
 }
 
 pub type HistoryHandle = UseStateHandle<History>;
@@ -95,11 +144,7 @@ pub fn tab_complete(input: &str) -> String {
     input.into()
 }
 
-pub fn get_program(
-    name: &str,
-    cwd: &PathBuf,
-    fs_tree: &FsTree,
-) -> Option<&'static Program> {
+pub fn get_program(name: &str, _cwd: &PathBuf, _fs_tree: &FsTree) -> Option<&'static Program> {
     if name.contains('/') {
         Some(&EXECUTE_FILE)
     } else {
@@ -107,33 +152,201 @@ pub fn get_program(
     }
 }
 
+/// Where a command's output goes instead of the screen.
+struct Redirect {
+    path: String,
+    append: bool,
+}
+
+/// Removes unquoted `>` and `>>` redirections from the words of a command, returning the last
+/// one.
+fn split_redirect(words: Vec<Word>) -> Result<(Vec<Word>, Option<Redirect>), String> {
+    let mut args = Vec::new();
+    let mut redirect = None;
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let (append, path) = match word.pattern().strip_prefix(">>") {
+            Some(path) => (true, path),
+            None => match word.pattern().strip_prefix('>') {
+                Some(path) => (false, path),
+                None => {
+                    args.push(word);
+                    continue;
+                }
+            },
+        };
+
+        let next;
+        let path = match path {
+            "" => {
+                next = words.next().ok_or_else(|| String::from("newline"))?;
+                next.pattern()
+            }
+            path => path,
+        };
+        let path = glob::unescape(&expand_tilde(path));
+        redirect = Some(Redirect { path, append });
+    }
+
+    Ok((args, redirect))
+}
+
+/// Expands an unquoted leading `~` in each word, then replaces each word containing unquoted glob
+/// metacharacters with the paths it matches, or keeps it as it is if nothing matches.
+fn expand_words(words: Vec<Word>, cwd: &Path, fs_tree: &FsTree) -> Vec<String> {
+    let mut args = Vec::new();
+    for word in words {
+        // a quoted `~` is escaped in the pattern, so it is left alone here
+        let pattern = expand_tilde(word.pattern());
+        let matches = if word.is_pattern() {
+            glob::expand(&pattern, cwd, fs_tree)
+        } else {
+            Vec::new()
+        };
+
+        if matches.is_empty() {
+            args.push(glob::unescape(&pattern));
+        } else {
+            args.extend(matches);
+        }
+    }
+    args
+}
+
+/// Opens the redirection's target before the command runs, creating it if needed and emptying
+/// it unless appending, as a shell does whether or not the command then succeeds.
+fn open_redirect(
+    redirect: &Redirect,
+    cwd: &Path,
+    fs_tree: &mut FsTree,
+) -> Result<FsIndex, FsError> {
+    let index = open_or_create(&redirect.path, cwd, fs_tree)?;
+    // devices have nothing to truncate, and emptying the clipboard would lose what's on it
+    if !redirect.append && fs_tree.is_file(index)? {
+        fs_tree.write(index, "")?;
+    }
+    Ok(index)
+}
+
+/// Writes the output of the command that just ran to the redirection's target, opened by
+/// [`open_redirect`].
+fn redirect_output(
+    redirect: &Redirect,
+    index: FsIndex,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> Result<(), FsError> {
+    let mut text = history
+        .take_output()
+        .map(|output| render_text(&output))
+        .unwrap_or_default();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    if redirect.append {
+        fs_tree.append(index, &text)
+    } else {
+        fs_tree.write(index, text.as_str())
+    }
+}
+
 pub fn submit_command(
     command: &str,
     cwd: &mut PathBuf,
     fs_tree: Rc<RefCell<FsTree>>,
     history: &mut History,
 ) -> StatusCode {
-    if let Ok(tokens) = shellish_parse::parse(command, false) {
-        let args = if tokens.is_empty() {
-            Vec::from([String::new()])
-        } else {
-            tokens
-        };
+    let words = match parse::split_words(command) {
+        Ok(words) => words,
+        Err(error) => {
+            let _ = history.write(html! {<>{format!("leash: syntax error: {error}")}</>});
+            return StatusCode(2);
+        }
+    };
 
-        let program = match get_program(args[0].as_str(), cwd, &fs_tree.borrow()) {
-            Some(f) => f,
-            None => {
-                history.write(html! {<>{format!("leash: command not found: {}", args[0])}</>});
+    let (words, redirect) = match split_redirect(words) {
+        Ok(split) => split,
+        Err(token) => {
+            let _ = history.write(
+                html! {<>{format!("leash: syntax error near unexpected token `{token}'")}</>},
+            );
+            return StatusCode(2);
+        }
+    };
+
+    let mut args = expand_words(words, cwd, &fs_tree.borrow());
+    if args.is_empty() {
+        args.push(String::new());
+    }
+
+    let redirect = match redirect {
+        Some(redirect) => match open_redirect(&redirect, cwd, &mut fs_tree.borrow_mut()) {
+            Ok(index) => Some((redirect, index)),
+            Err(error) => {
+                let _ = history.write(html! {<>{format!("leash: {error}: {}", redirect.path)}</>});
                 return StatusCode(1);
             }
-        };
+        },
+        None => None,
+    };
 
-        program(&args, cwd, &mut fs_tree.borrow_mut(), history)
-    } else {
-        unimplemented!();
+    let program = match get_program(args[0].as_str(), cwd, &fs_tree.borrow()) {
+        Some(f) => f,
+        None => {
+            let _ = history.write(html! {<>{format!("leash: command not found: {}", args[0])}</>});
+            return StatusCode(1);
+        }
+    };
+
+    proc::record(history, cwd);
+    let status = program(&args, cwd, &mut fs_tree.borrow_mut(), history);
+
+    let Some((redirect, index)) = redirect else {
+        return status;
+    };
+    match redirect_output(&redirect, index, &mut fs_tree.borrow_mut(), history) {
+        Ok(()) => status,
+        Err(error) => {
+            let _ = history.write(html! {<>{format!("leash: {error}: {}", redirect.path)}</>});
+            StatusCode(1)
+        }
     }
 }
 
+/// Flattens program output to plain text, e.g. for redirection into a file. Line breaks become
+/// newlines, and components, which only render in the browser, contribute nothing.
+pub fn render_text(output: &Html) -> String {
+    fn push_text(text: &mut String, node: &VNode) {
+        match node {
+            VNode::VTag(tag) if tag.tag() == "br" => text.push('\n'),
+            VNode::VTag(tag) => {
+                if let Some(children) = tag.children() {
+                    push_text(text, children);
+                }
+            }
+            VNode::VText(vtext) => text.push_str(&vtext.text),
+            VNode::VList(list) => list.iter().for_each(|child| push_text(text, child)),
+            VNode::VRaw(raw) => {
+                let mut in_tag = false;
+                for c in raw.html.chars() {
+                    match c {
+                        '<' => in_tag = true,
+                        '>' => in_tag = false,
+                        c if !in_tag => text.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut text = String::new();
+    push_text(&mut text, output);
+    text
+}
+
 pub fn display_path(path: &Path) -> Rc<str> {
     // TODO justify this
     let path = path.to_string_lossy();
@@ -145,38 +358,185 @@ pub fn display_path(path: &Path) -> Rc<str> {
     .into()
 }
 
-pub fn canonicalize(path: &PathBuf, fs_tree: &FsTree) -> Result<PathBuf, ()> {
-    if path.is_relative() {
-        unimplemented!()
+/// Expands a leading `~` or `~/` to the home directory.
+pub fn expand_tilde(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = env::var("HOME").unwrap_or_else(|| HOME.into());
+            format!("{home}{rest}").into()
+        }
+        _ => path.into(),
     }
+}
 
-    let mut out = PathBuf::from("/");
+/// Resolves a path argument against `cwd`, returning the node it names and its canonical
+/// absolute path. Every program should look up paths through here.
+pub fn resolve_path(
+    path: &str,
+    cwd: &Path,
+    fs_tree: &FsTree,
+) -> Result<(FsIndex, PathBuf), FsError> {
+    fs_tree.resolve(Path::new(path), cwd)
+}
 
-    let mut current = fs_tree.root();
-    for component in path
-        .iter()
-        .skip(1)
-        .filter(|c| !c.is_empty() && *c != UnixStr::new("."))
-    {
-        if component == UnixStr::new("..") {
-            out.pop();
-        } else {
-            out.push(component);
-        }
-        match fs_tree.iter_dir(current) {
-            Ok(mut iter) => {
-                current = iter
-                    .find(|entry| entry.name().as_ref() == component.to_str().unwrap())
-                    .ok_or(())?
-                    .index();
-            }
-            Err(_) => unimplemented!(),
+/// Like [`resolve_path`], but a symbolic link named by the final component is not followed.
+pub fn resolve_path_nofollow(
+    path: &str,
+    cwd: &Path,
+    fs_tree: &FsTree,
+) -> Result<(FsIndex, PathBuf), FsError> {
+    fs_tree.resolve_nofollow(Path::new(path), cwd)
+}
+
+/// Resolves every component of a path argument except the last, returning the parent directory
+/// and the final name. Used by programs that create, rename or remove the entry itself.
+pub fn resolve_parent(
+    path: &str,
+    cwd: &Path,
+    fs_tree: &FsTree,
+) -> Result<(FsIndex, String), FsError> {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(FsError::InvalidName)?;
+    let parent = path.parent().ok_or(FsError::InvalidName)?;
+
+    let (parent, _) = fs_tree.resolve(parent, cwd)?;
+    Ok((parent, name.into()))
+}
+
+/// Resolves a path argument naming a file to write, creating an empty file there if nothing
+/// exists yet.
+pub fn open_or_create(path: &str, cwd: &Path, fs_tree: &mut FsTree) -> Result<FsIndex, FsError> {
+    match resolve_path(path, cwd, fs_tree) {
+        Ok((index, _)) => Ok(index),
+        Err(FsError::NotFound) => {
+            let (parent, name) = resolve_parent(path, cwd, fs_tree)?;
+            fs_tree.create_file(&name, parent)
         }
+        Err(error) => Err(error),
     }
+}
 
-    Ok(out)
+/// Resets `cwd` to the root if it no longer names anything, as after the directory is removed or
+/// the tree is replaced.
+pub fn validate_cwd(cwd: &mut PathBuf, fs_tree: &FsTree) {
+    if fs_tree.lookup_path(cwd).is_err() {
+        *cwd = PathBuf::from("/");
+    }
 }
 
+/// Mounts the changes saved by a previous visit over the site content, which forms a read-only
+/// lower layer.
 pub fn init_fs() -> FsTree {
-    FsTree::default()
+    let lower = Rc::new(FsTree::default());
+    let upper = match persist::load() {
+        Ok(upper) => upper.unwrap_or_default(),
+        Err(PersistError::Unavailable) => UpperLayer::default(),
+        Err(error) => {
+            console::warn_1(&format!("discarding stored filesystem: {error}").into());
+            let _ = persist::discard(true);
+            UpperLayer::default()
+        }
+    };
+
+    // quotas aren't enforced while restoring, so only a malformed layer is discarded here
+    let fs_tree = FsTree::overlay(Rc::clone(&lower), &upper).unwrap_or_else(|error| {
+        console::warn_1(&format!("discarding stored filesystem: {error}").into());
+        let _ = persist::discard(true);
+        FsTree::overlay(lower, &UpperLayer::default()).expect("an empty layer always applies")
+    });
+    if fs_tree.usage().exceeds(&fs_tree.quota()) {
+        console::warn_1(&"stored filesystem is over quota; it can shrink but not grow".into());
+    }
+    fs_tree
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+    use yew::html;
+
+    use super::{ExecutionRecord, History, StatusCode};
+    use crate::{fs::FsTree, programs::tests::run};
+
+    #[test]
+    fn redirection_opens_its_target_before_the_command_runs() {
+        let fs_tree = Rc::new(RefCell::new(FsTree::new()));
+        let mut cwd = PathBuf::from("/");
+        let read = |path: &str| {
+            let fs_tree = fs_tree.borrow();
+            fs_tree
+                .read_text(fs_tree.lookup_path(unix_path::Path::new(path)).unwrap())
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            run("echo hi > out", &mut cwd, &fs_tree),
+            (StatusCode(0), String::new())
+        );
+        assert_eq!(read("/out"), "hi\n");
+
+        // a failing command still truncates the target, and its output goes there
+        let (status, _) = run("cat missing > out", &mut cwd, &fs_tree);
+        assert!(status.is_failure());
+        assert_eq!(
+            read("/out"),
+            "leash: cat: no such file or directory: missing\n"
+        );
+        let (status, _) = run("nonexistent > created", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(1));
+        assert_eq!(read("/created"), "");
+
+        // appending keeps what was there
+        run("echo one > log", &mut cwd, &fs_tree);
+        run("cat missing >> log", &mut cwd, &fs_tree);
+        assert!(read("/log").starts_with("one\nleash: cat:"));
+
+        // a target that can't be opened stops the command from running at all
+        let (status, output) = run("echo hi > nowhere/out", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(1));
+        assert_eq!(output, "leash: no such file or directory: nowhere/out");
+    }
+
+    #[test]
+    fn only_unquoted_tildes_expand() {
+        let fs_tree = Rc::new(RefCell::new(FsTree::new()));
+        let mut cwd = PathBuf::from("/");
+        let mut echo = |args: &str| run(&format!("echo {args}"), &mut cwd, &fs_tree).1;
+
+        assert_eq!(echo("~ ~/notes"), "/home/user /home/user/notes");
+        assert_eq!(echo(r#"'~' "~/notes" \~ a~"#), "~ ~/notes ~ a~");
+    }
+
+    fn record(command: &str) -> ExecutionRecord {
+        ExecutionRecord::new(StatusCode(0), "~", command, None)
+    }
+
+    #[test]
+    fn late_output_follows_its_record() {
+        let mut history = History(vec![record("ls"), record("upload")]);
+        let upload = history.0[1].id();
+
+        history.clear();
+        history.0.extend([record("pwd"), record("cd")]);
+        history.append_output(upload, html! {"saved"});
+        assert!(history.0.iter().all(|record| record.output().is_none()));
+
+        let mut history = History(vec![record("upload")]);
+        let upload = history.0[0].id();
+        history.0.insert(0, record("ls"));
+        history.0.push(record("pwd"));
+        history.append_output(upload, html! {"saved"});
+        let written = history
+            .0
+            .iter()
+            .map(|record| record.output().is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(written, [false, true, false]);
+    }
 }
@@ -1,9 +1,13 @@
 use site::{
-    components::{Fastfetch, Prompt}, display_path, fs::FsTree, init_fs, submit_command, tab_complete, ExecutionRecord, History, StatusCode, HOME
+    ExecutionRecord, HOME, History, RecordId, StatusCode,
+    components::{Fastfetch, Prompt},
+    display_path, fs, init_fs, persist, submit_command, tab_complete,
+    transfer::{self, Action, Request, Upload},
+    validate_cwd,
 };
-use unix_path::{Path, PathBuf};
+use unix_path::PathBuf;
 use wasm_bindgen::JsCast;
-use web_sys::HtmlInputElement;
+use web_sys::{ClipboardEvent, DragEvent, Element, FileList, HtmlInputElement};
 use yew::prelude::*;
 
 #[function_component]
@@ -12,7 +16,8 @@ fn Ash() -> Html {
 
     let cwd_handle = use_state(|| PathBuf::from(HOME));
     let status_handle = use_state(|| StatusCode(0));
-    let history_handle = use_state(|| {
+    // kept outside of state so that uploads finishing later can add to the latest history
+    let history = use_mut_ref(|| {
         History(vec![ExecutionRecord::new(
             StatusCode(0),
             &display_path(&cwd_handle),
@@ -21,12 +26,84 @@ fn Ash() -> Html {
         )])
     });
     let input_handle = use_state(String::default);
+    let force_update = use_force_update();
 
-    let handle_keydown = {
+    // reads `files` and carries out `action` on them, reporting progress and then the results
+    // under the history record `record`, whether the files were picked or dropped
+    let read_uploads = {
+        let fs_tree = fs_tree.clone();
+        let history = history.clone();
+        let force_update = force_update.clone();
+
+        move |files: FileList, action: Action, record: RecordId| {
+            history
+                .borrow_mut()
+                .append_output(record, html! {<>{transfer::uploading(files.length())}</>});
+            force_update.force_update();
+
+            let fs_tree = fs_tree.clone();
+            let history = history.clone();
+            let force_update = force_update.clone();
+            transfer::read_files(&files, move |uploads: Vec<Upload>| {
+                let output = action.complete(uploads, &mut fs_tree.borrow_mut());
+                history.borrow_mut().append_output(record, output);
+                persist::schedule_save(fs_tree.clone());
+                force_update.force_update();
+            });
+        }
+    };
+
+    let run_command = {
         let cwd_handle = cwd_handle.clone();
         let status_handle = status_handle.clone();
-        let history_handle = history_handle.clone();
+        let history = history.clone();
         let input_handle = input_handle.clone();
+        let force_update = force_update.clone();
+        let read_uploads = read_uploads.clone();
+
+        Callback::from(move |command: String| {
+            let mut cwd = (*cwd_handle).clone();
+            let mut history_ref = history.borrow_mut();
+
+            history_ref.0.push(ExecutionRecord::new(
+                *status_handle,
+                &display_path(&cwd),
+                command.as_str(),
+                None,
+            ));
+
+            let revision = fs_tree.borrow().revision();
+            status_handle.set(submit_command(
+                &command,
+                &mut cwd,
+                fs_tree.clone(),
+                &mut history_ref,
+            ));
+            drop(history_ref);
+            if fs_tree.borrow().revision() != revision {
+                persist::schedule_save(fs_tree.clone());
+            }
+
+            // make sure cwd still exists, if not reset to root
+            validate_cwd(&mut cwd, &fs_tree.borrow());
+            cwd_handle.set(cwd);
+
+            // a command may have asked for files; the picker can only be opened from here
+            if let Some(Request { action, record }) = transfer::take_request() {
+                let read_uploads = read_uploads.clone();
+                transfer::pick_files(action.accepts_multiple(), move |files| {
+                    read_uploads(files, action, record)
+                });
+            }
+
+            input_handle.set(String::new());
+            force_update.force_update();
+        })
+    };
+
+    let handle_keydown = {
+        let input_handle = input_handle.clone();
+        let run_command = run_command.clone();
 
         Callback::from(move |e: KeyboardEvent| match e.key().as_str() {
             "Tab" => {
@@ -42,48 +119,82 @@ fn Ash() -> Html {
             }
             "Enter" => {
                 e.prevent_default();
-                let command = e
-                    .target()
-                    .unwrap()
-                    .dyn_into::<HtmlInputElement>()
-                    .unwrap()
-                    .value();
-
-                let mut cwd = (*cwd_handle).clone();
-                let mut history = (*history_handle).clone();
-
-                history.0.push(ExecutionRecord::new(
-                    *status_handle,
-                    &display_path(&cwd),
-                    command.as_str(),
-                    None,
-                ));
+                run_command.emit(
+                    e.target()
+                        .unwrap()
+                        .dyn_into::<HtmlInputElement>()
+                        .unwrap()
+                        .value(),
+                );
+            }
+            _ => (),
+        })
+    };
 
-                status_handle.set(submit_command(
-                    &command,
-                    &mut cwd,
-                    fs_tree.clone(),
-                    &mut history,
-                ));
+    // keep /dev/clipboard in step with whatever the visitor copies or pastes on the page
+    let handle_copy = Callback::from(|_: Event| {
+        if let Some(selection) =
+            web_sys::window().and_then(|window| window.get_selection().ok().flatten())
+        {
+            fs::set_clipboard(&String::from(selection.to_string()));
+        }
+    });
+    let handle_paste = Callback::from(|e: Event| {
+        if let Some(text) = e
+            .dyn_into::<ClipboardEvent>()
+            .ok()
+            .and_then(|e| e.clipboard_data())
+            .and_then(|data| data.get_data("text").ok())
+        {
+            fs::set_clipboard(&text);
+        }
+    });
 
-                // make sure cwd still exists, if not reset to root
-                if fs_tree.borrow().lookup_path(&cwd).is_some() {
-                    cwd_handle.set(cwd);
-                } else {
-                    cwd_handle.set(PathBuf::from("/"));
-                };
+    // files dropped on the page are uploaded into the working directory, as if by `upload`
+    let handle_dragover = Callback::from(|e: DragEvent| e.prevent_default());
+    let handle_drop = {
+        let cwd_handle = cwd_handle.clone();
+        let status_handle = status_handle.clone();
+        let history = history.clone();
 
-                input_handle.set(String::new());
-                history_handle.set(history);
+        Callback::from(move |e: DragEvent| {
+            e.prevent_default();
+            let Some(files) = e.data_transfer().and_then(|data| data.files()) else {
+                return;
+            };
+            if files.length() == 0 {
+                return;
             }
-            _ => (),
+
+            let record =
+                ExecutionRecord::new(*status_handle, &display_path(&cwd_handle), "upload", None);
+            let record_id = record.id();
+            history.borrow_mut().0.push(record);
+
+            let action = Action::Upload {
+                cwd: (*cwd_handle).clone(),
+            };
+            read_uploads(files, action, record_id);
         })
     };
 
+    // output can offer commands to run when clicked, such as `search` opening a result
+    let handle_click = Callback::from(move |e: MouseEvent| {
+        if let Some(command) = e
+            .target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+            .and_then(|element| element.closest("[data-command]").ok().flatten())
+            .and_then(|element| element.get_attribute("data-command"))
+        {
+            run_command.emit(command);
+        }
+    });
+
+    let history = history.borrow();
     html! {
-        <div>
+        <div class={classes!("min-h-screen")} oncopy={handle_copy} onclick={handle_click} ondragover={handle_dragover} ondrop={handle_drop}>
             {
-                for history_handle.0.iter().map(|record| {
+                for history.0.iter().map(|record| {
                     html! {
                         <>
                             <Prompt status={record.last_status()} cwd_display={record.cwd_display()} />
@@ -99,7 +210,7 @@ fn Ash() -> Html {
                 })
             }
             <Prompt status={*status_handle} cwd_display={display_path(&(*cwd_handle).clone())} />
-            <input id={"commandInput"} class={classes!("focus:outline-none")} onkeydown={handle_keydown} value={(*input_handle).clone()} />
+            <input id={"commandInput"} class={classes!("focus:outline-none")} onkeydown={handle_keydown} onpaste={handle_paste} value={(*input_handle).clone()} />
         </div>
     }
 }
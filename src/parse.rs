@@ -0,0 +1,188 @@
+//! Splits command lines into words, keeping track of what was quoted.
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+use crate::glob;
+
+/// Characters that keep a special meaning after quote removal, so a quoted occurrence of one is
+/// escaped with a backslash: the glob metacharacters, `>` for redirection, `~` for tilde
+/// expansion, and the backslash itself.
+const SPECIAL: &[char] = &['*', '?', '[', ']', '>', '~', '\\'];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("unterminated quote: {0}")]
+    UnterminatedQuote(char),
+    #[error("unexpected end of input after `\\`")]
+    DanglingBackslash,
+}
+
+/// A word of a command line after quote removal. Quoted characters that would otherwise be
+/// special are escaped with a backslash, so a word can be used as a glob pattern as it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Word(String);
+
+impl Word {
+    /// The word as a pattern, with quoted special characters escaped.
+    pub fn pattern(&self) -> &str {
+        &self.0
+    }
+
+    /// The word as its program sees it.
+    pub fn text(&self) -> String {
+        glob::unescape(&self.0)
+    }
+
+    /// Whether the word has unquoted glob metacharacters, and is therefore subject to pathname
+    /// expansion.
+    pub fn is_pattern(&self) -> bool {
+        glob::is_pattern(&self.0)
+    }
+}
+
+fn push_literal(word: &mut String, c: char) {
+    if SPECIAL.contains(&c) {
+        word.push('\\');
+    }
+    word.push(c);
+}
+
+/// Splits `input` into words on unquoted whitespace, in the manner of a POSIX shell: single
+/// quotes preserve everything, double quotes preserve everything but backslash escapes of `"`,
+/// `\`, `$` and `` ` ``, and an unquoted backslash preserves the next character. An unquoted `#`
+/// at the start of a word begins a comment, and an unquoted `>` or `>>` always starts a new word,
+/// so that `a>b` redirects as `a >b` does.
+pub fn split_words(input: &str) -> Result<Vec<Word>, ParseError> {
+    let mut words = Vec::new();
+    // `None` between words, so that `""` still makes an empty word
+    let mut word: Option<String> = None;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take().map(Word)),
+            '#' if word.is_none() => break,
+            '>' => {
+                if word.as_deref() != Some(">") {
+                    words.extend(word.take().map(Word));
+                }
+                word.get_or_insert_default().push('>');
+            }
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => push_literal(word, c),
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => push_literal(word, c),
+                            Some(c) => {
+                                push_literal(word, '\\');
+                                push_literal(word, c);
+                            }
+                            None => return Err(ParseError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => push_literal(word, c),
+                        None => return Err(ParseError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => push_literal(word.get_or_insert_default(), c),
+                None => return Err(ParseError::DanglingBackslash),
+            },
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+
+    words.extend(word.map(Word));
+    Ok(words)
+}
+
+/// Quotes `text` so that [`split_words`] reads it back as a single word with the same text.
+pub fn quote(text: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_alphanumeric() || "/._-+,:=@%~".contains(c);
+    if !text.is_empty() && text.chars().all(plain) && !text.starts_with('~') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(format!("'{}'", text.replace('\'', r"'\''")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseError, Word, split_words};
+
+    fn patterns(input: &str) -> Vec<String> {
+        split_words(input)
+            .unwrap()
+            .iter()
+            .map(|word| word.pattern().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_unquoted_whitespace() {
+        assert_eq!(patterns("  ls   -l\tnotes  "), ["ls", "-l", "notes"]);
+        assert_eq!(patterns("echo '' \"\""), ["echo", "", ""]);
+        assert_eq!(patterns("echo a# b # comment"), ["echo", "a#", "b"]);
+    }
+
+    #[test]
+    fn escapes_quoted_special_characters() {
+        assert_eq!(
+            patterns("ls '*.txt' \"a?\" [b]"),
+            ["ls", r"\*.txt", r"a\?", "[b]"]
+        );
+        assert_eq!(
+            patterns(r"echo \* \\ 'it'\''s'"),
+            ["echo", r"\*", r"\\", "it's"]
+        );
+        assert_eq!(patterns(r#"echo "\"\$x\n""#), ["echo", r#""$x\\n"#]);
+
+        let words = split_words("echo 'a b*'").unwrap();
+        assert_eq!(words[1].text(), "a b*");
+        assert!(!words[1].is_pattern());
+    }
+
+    #[test]
+    fn splits_redirections_from_words() {
+        assert_eq!(patterns("echo a>b"), ["echo", "a", ">b"]);
+        assert_eq!(patterns("echo a>>b"), ["echo", "a", ">>b"]);
+        assert_eq!(patterns("echo a > b"), ["echo", "a", ">", "b"]);
+        assert_eq!(patterns("echo 'a>b' a\\>b"), ["echo", r"a\>b", r"a\>b"]);
+    }
+
+    #[test]
+    fn escapes_quoted_tildes() {
+        assert_eq!(
+            patterns(r#"cd ~ '~' "~/a" \~ a~"#),
+            ["cd", "~", r"\~", r"\~/a", r"\~", "a~"]
+        );
+        assert_eq!(split_words("cd '~'").unwrap()[1].text(), "~");
+    }
+
+    #[test]
+    fn rejects_unterminated_input() {
+        assert_eq!(
+            split_words("echo 'a"),
+            Err(ParseError::UnterminatedQuote('\''))
+        );
+        assert_eq!(
+            split_words("echo \"a"),
+            Err(ParseError::UnterminatedQuote('"'))
+        );
+        assert_eq!(split_words("echo a\\"), Err(ParseError::DanglingBackslash));
+        assert_eq!(split_words(""), Ok(Vec::<Word>::new()));
+    }
+}
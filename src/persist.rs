@@ -0,0 +1,266 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gloo_timers::callback::Timeout;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use thiserror::Error;
+use web_sys::{Storage, console};
+
+use crate::fs::{FsError, FsTree, UpperLayer};
+
+const STORAGE_KEY: &str = "fs_tree";
+/// Where stored data that could not be read is moved, so it is never silently lost.
+const BACKUP_KEY: &str = "fs_tree.unreadable";
+const SAVE_DELAY_MS: u32 = 500;
+
+/// Version of the stored layout. Bump this whenever the serialized form of `UpperLayer` changes,
+/// and teach [`upgrade`] how to turn the previous version into the new one.
+pub const SCHEMA_VERSION: u64 = 4;
+
+#[derive(Debug, Error)]
+pub enum PersistError {
+    #[error("localStorage is unavailable")]
+    Unavailable,
+    #[error("localStorage rejected the operation")]
+    Storage,
+    #[error("malformed filesystem data: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("filesystem data does not fit the site content: {0}")]
+    Filesystem(#[from] FsError),
+    #[error("unsupported schema version {0}")]
+    UnsupportedVersion(u64),
+    #[error("could not upgrade filesystem from schema version {0}")]
+    Upgrade(u64),
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u64,
+    data: &'a UpperLayer,
+}
+
+/// An envelope whose data may still be in an older layout. Before version 3 the data was a
+/// whole tree, stored under `tree`.
+#[derive(Deserialize)]
+struct StoredEnvelope {
+    version: u64,
+    #[serde(alias = "tree")]
+    data: Value,
+}
+
+thread_local! {
+    static PENDING_SAVE: RefCell<Option<Timeout>> = const { RefCell::new(None) };
+}
+
+fn local_storage() -> Result<Storage, PersistError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or(PersistError::Unavailable)
+}
+
+/// Saves the changes made on top of the site content. The content itself is never stored, so
+/// returning visitors see it as currently deployed.
+pub fn save(fs_tree: &FsTree) -> Result<(), PersistError> {
+    let serialized = serde_json::to_string(&Envelope {
+        version: SCHEMA_VERSION,
+        data: &fs_tree.upper_layer()?,
+    })?;
+
+    local_storage()?
+        .set_item(STORAGE_KEY, &serialized)
+        .map_err(|_| PersistError::Storage)
+}
+
+/// Saves the tree once no further call has been made for a short while, so a burst of commands
+/// results in a single write.
+pub fn schedule_save(fs_tree: Rc<RefCell<FsTree>>) {
+    let timeout = Timeout::new(SAVE_DELAY_MS, move || {
+        if let Err(error) = save(&fs_tree.borrow()) {
+            console::warn_1(&format!("failed to save filesystem: {error}").into());
+        }
+    });
+
+    // replacing the previous timeout drops, and thereby cancels, it
+    PENDING_SAVE.with_borrow_mut(|pending| *pending = Some(timeout));
+}
+
+/// Loads the stored upper layer, upgrading it from older schema versions as needed. Returns
+/// `None` if nothing has been stored yet.
+pub fn load() -> Result<Option<UpperLayer>, PersistError> {
+    let Some(serialized) = local_storage()?
+        .get_item(STORAGE_KEY)
+        .map_err(|_| PersistError::Storage)?
+    else {
+        return Ok(None);
+    };
+
+    let StoredEnvelope {
+        mut version,
+        mut data,
+    } = serde_json::from_str(&serialized)?;
+    if version > SCHEMA_VERSION {
+        return Err(PersistError::UnsupportedVersion(version));
+    }
+
+    while version < SCHEMA_VERSION {
+        data = upgrade(version, data)?;
+        version += 1;
+    }
+
+    Ok(Some(serde_json::from_value(data)?))
+}
+
+/// Converts serialized data from schema `version` to `version + 1`.
+fn upgrade(version: u64, data: Value) -> Result<Value, PersistError> {
+    match version {
+        1 => upgrade_v1(data).ok_or(PersistError::Upgrade(version)),
+        2 => upgrade_v2(data),
+        // version 4 added binary file contents; text contents are stored as before
+        3 => Ok(data),
+        _ => Err(PersistError::UnsupportedVersion(version)),
+    }
+}
+
+/// Version 2 made `FsIndex` generational: every index gained a generation, and every slot of
+/// the node table records the generation it is currently on.
+fn upgrade_v1(mut tree: Value) -> Option<Value> {
+    let index = |slot: Value| json!({ "slot": slot, "generation": 0 });
+
+    let node_table = tree.get_mut("node_table")?.as_array_mut()?;
+    for slot in node_table.iter_mut() {
+        if let Some(entries) = slot
+            .pointer_mut("/node/Directory/entries")
+            .and_then(Value::as_object_mut)
+        {
+            for entry in entries.values_mut() {
+                *entry = index(entry.take());
+            }
+        }
+
+        *slot = json!({ "generation": 0, "inode": slot.take() });
+    }
+
+    Some(tree)
+}
+
+/// Version 3 stores only the visitor's changes on top of the site content rather than the
+/// whole tree, so the stored tree is diffed against the current content. The current content
+/// has gained entries version 2 never saw, such as `/dev` and `/proc`, so anything missing from
+/// the stored tree is kept rather than hidden. This relies on `FsTree` still deserializing the
+/// version 2 layout.
+fn upgrade_v2(tree: Value) -> Result<Value, PersistError> {
+    let tree: FsTree = serde_json::from_value(tree)?;
+    let upper = UpperLayer::without_whiteouts(&FsTree::default(), &tree)?;
+    Ok(serde_json::to_value(upper)?)
+}
+
+/// Moves the stored changes out of the way so the next load starts from the site content alone.
+pub fn discard(backup: bool) -> Result<(), PersistError> {
+    let storage = local_storage()?;
+    if backup && let Ok(Some(serialized)) = storage.get_item(STORAGE_KEY) {
+        storage
+            .set_item(BACKUP_KEY, &serialized)
+            .map_err(|_| PersistError::Storage)?;
+    }
+
+    PENDING_SAVE.with_borrow_mut(Option::take);
+    storage
+        .remove_item(STORAGE_KEY)
+        .map_err(|_| PersistError::Storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use serde_json::json;
+    use unix_path::Path;
+
+    use super::{Envelope, SCHEMA_VERSION, StoredEnvelope, upgrade};
+    use crate::fs::{FsTree, UpperLayer};
+
+    /// A tree as version 2 stored it: the site content of the time with a note added, and
+    /// neither `/dev` nor `/proc`.
+    fn v2_tree() -> serde_json::Value {
+        let slot = |slot: usize| json!({ "slot": slot, "generation": 0 });
+        let directory = |entries: serde_json::Value| {
+            json!({
+                "generation": 0,
+                "inode": {
+                    "node": { "Directory": { "entries": entries } },
+                    "mode": 0o755, "owner": "user", "created": 0, "modified": 0,
+                },
+            })
+        };
+        json!({
+            "node_table": [
+                directory(json!({ ".": slot(0), "..": slot(0), "home": slot(1) })),
+                directory(json!({ ".": slot(1), "..": slot(0), "user": slot(2) })),
+                directory(json!({ ".": slot(2), "..": slot(1), "notes.txt": slot(3) })),
+                {
+                    "generation": 0,
+                    "inode": {
+                        "node": { "File": { "contents": "my notes" } },
+                        "mode": 0o644, "owner": "user", "created": 0, "modified": 0,
+                    },
+                },
+            ],
+            "vacancies": [],
+        })
+    }
+
+    #[test]
+    fn v2_tree_keeps_content_it_never_had() {
+        let data = upgrade(3, upgrade(2, v2_tree()).unwrap()).unwrap();
+        let upper: UpperLayer = serde_json::from_value(data).unwrap();
+        assert_eq!(upper.whiteouts(), 0);
+
+        let fs_tree = FsTree::overlay(Rc::new(FsTree::default()), &upper).unwrap();
+        for path in ["/dev/null", "/proc/uptime", "/srv/docs/resume.md"] {
+            assert!(
+                fs_tree.resolve(Path::new(path), Path::new("/")).is_ok(),
+                "{path} is missing"
+            );
+        }
+        let (notes, _) = fs_tree
+            .resolve(Path::new("/home/user/notes.txt"), Path::new("/"))
+            .unwrap();
+        assert_eq!(&*fs_tree.read_text(notes).unwrap(), "my notes");
+    }
+
+    #[test]
+    fn hard_links_survive_a_round_trip() {
+        let lower = Rc::new(FsTree::default());
+        let mut fs_tree = FsTree::overlay(Rc::clone(&lower), &UpperLayer::default()).unwrap();
+        let (home, _) = fs_tree
+            .resolve(Path::new("/home/user"), Path::new("/"))
+            .unwrap();
+        let notes = fs_tree.create_file("notes", home).unwrap();
+        fs_tree.write(notes, "shared").unwrap();
+        fs_tree.create_hard_link("again", home, notes).unwrap();
+
+        let serialized = serde_json::to_string(&Envelope {
+            version: SCHEMA_VERSION,
+            data: &fs_tree.upper_layer().unwrap(),
+        })
+        .unwrap();
+        let stored: StoredEnvelope = serde_json::from_str(&serialized).unwrap();
+        let upper: UpperLayer = serde_json::from_value(stored.data).unwrap();
+        let mut reloaded = FsTree::overlay(lower, &upper).unwrap();
+
+        let resolve = |fs_tree: &FsTree, path: &str| {
+            fs_tree.resolve(Path::new(path), Path::new("/")).unwrap().0
+        };
+        let notes = resolve(&reloaded, "/home/user/notes");
+        assert_eq!(resolve(&reloaded, "/home/user/again"), notes);
+        // link counts aren't stored, so they must be rebuilt from the entries
+        assert_eq!(reloaded.metadata(notes).unwrap().links(), 2);
+        assert_eq!(reloaded.usage(), fs_tree.usage());
+        assert!(reloaded.check().is_empty());
+
+        let home = resolve(&reloaded, "/home/user");
+        reloaded.delete("notes", home).unwrap();
+        assert_eq!(&*reloaded.read_text(notes).unwrap(), "shared");
+        assert_eq!(reloaded.metadata(notes).unwrap().links(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{cell::RefCell, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+use unix_path::{Path, PathBuf};
+
+use crate::{
+    History, StatusCode,
+    fs::{FileType, FsError, FsTree, now},
+};
+
+/// The shell state `/proc` exposes, as of the command currently running.
+struct ShellState {
+    commands: Vec<Rc<str>>,
+    cwd: PathBuf,
+    last_status: StatusCode,
+}
+
+thread_local! {
+    static SHELL_STATE: RefCell<ShellState> = RefCell::new(ShellState {
+        commands: Vec::new(),
+        cwd: PathBuf::from("/"),
+        last_status: StatusCode(0),
+    });
+    static BOOT_TIME: u64 = now();
+}
+
+/// Updates the state behind `/proc`. Called before each command runs, so the command sees
+/// itself in `/proc/history` and the status of the one before it in `/proc/self/status`.
+pub fn record(history: &History, cwd: &Path) {
+    SHELL_STATE.with_borrow_mut(|state| {
+        state.commands = history.0.iter().map(|record| record.command()).collect();
+        state.cwd = cwd.to_path_buf();
+        state.last_status = history
+            .0
+            .last()
+            .map_or(StatusCode(0), |record| record.last_status());
+    });
+}
+
+/// The files under `/proc`, each generated from the shell state when read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Generator {
+    History,
+    Uptime,
+    Version,
+    Cwd,
+    Status,
+}
+
+impl Generator {
+    pub fn file_type(&self) -> FileType {
+        match self {
+            Generator::Cwd => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+
+    /// The contents of the file, or the target of the link.
+    pub fn generate(&self) -> Rc<str> {
+        match self {
+            Generator::History => SHELL_STATE.with_borrow(|state| {
+                state
+                    .commands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, command)| format!("{:>5}  {command}\n", i + 1))
+                    .collect::<String>()
+                    .into()
+            }),
+            Generator::Uptime => {
+                let seconds = now().saturating_sub(BOOT_TIME.with(|boot| *boot)) as f64 / 1000.0;
+                format!("{seconds:.2} 0.00\n").into()
+            }
+            Generator::Version => concat!("leash version ", env!("CARGO_PKG_VERSION"), "\n").into(),
+            Generator::Cwd => SHELL_STATE.with_borrow(|state| state.cwd.to_string_lossy().into()),
+            Generator::Status => SHELL_STATE.with_borrow(|state| {
+                format!("Name:\tleash\nExitStatus:\t{}\n", state.last_status.0).into()
+            }),
+        }
+    }
+}
+
+/// Creates `/proc` and the generated files beneath it.
+pub fn mount(fs_tree: &mut FsTree) -> Result<(), FsError> {
+    // uptime counts from the first mount
+    BOOT_TIME.with(|_| {});
+
+    let root = fs_tree.root();
+    let proc = fs_tree.create_directory("proc", root)?;
+    let this = fs_tree.create_directory("self", proc)?;
+    for (name, parent, generator) in [
+        ("history", proc, Generator::History),
+        ("uptime", proc, Generator::Uptime),
+        ("version", proc, Generator::Version),
+        ("cwd", this, Generator::Cwd),
+        ("status", this, Generator::Status),
+    ] {
+        fs_tree.create_generated(name, parent, generator)?;
+    }
+
+    fs_tree.set_mode(this, 0o555)?;
+    fs_tree.set_mode(proc, 0o555)
+}
@@ -1,96 +1,1068 @@
-use std::{cmp::Reverse, collections::BinaryHeap, rc::Rc};
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 use phf::phf_map;
 use unix_path::{Path, PathBuf};
-use wasm_bindgen::JsValue;
-use web_sys::console;
-use yew::{Html, html, classes};
+use yew::{Html, classes, html};
 
 use crate::{
-    canonicalize, components::Fastfetch, fs::{FsIndex, FsTree}, ExecutionRecord, History, HistoryHandle, StatusCode
+    HOME, History, StatusCode,
+    components::Fastfetch,
+    display_path, env,
+    fs::{Contents, DateTime, FsError, FsIndex, FsTree},
+    persist::{self, PersistError},
+    resolve_parent, resolve_path, resolve_path_nofollow,
+    tar::TarError,
+    transfer::{self, Action},
 };
 
+mod du;
+mod find;
+mod grep;
+mod search;
+mod snapshot;
+mod tar;
+mod tree;
+
 pub type Program = fn(&[String], &mut PathBuf, &mut FsTree, &mut History) -> StatusCode;
 
 pub const PROGRAMS: phf::Map<&'static str, Program> = phf_map! {
+    "cat" => cat,
     "cd" => cd,
+    "chmod" => chmod,
     "clear" => clear,
+    "df" => df,
+    "download" => download,
+    "du" => du::du,
     "echo" => echo,
+    "find" => find::find,
+    "fsck" => fsck,
+    "grep" => grep::grep,
     "help" => help,
+    "ln" => ln,
     "ls" => ls,
+    "mount" => mount,
+    "mv" => mv,
+    "readlink" => readlink,
+    "realpath" => realpath,
+    "reset" => reset,
+    "restore" => snapshot::restore,
+    "rm" => rm,
+    "rmdir" => rmdir,
+    "search" => search::search,
+    "snapshot" => snapshot::snapshot,
+    "stat" => stat,
+    "tar" => tar::tar,
+    "tree" => tree::tree,
+    "upload" => upload,
     "fastfetch" => fastfetch,
 };
 
+fn error_message(program: &str, error: FsError, subject: &str) -> String {
+    format!("leash: {program}: {error}: {subject}")
+}
+
+fn report_error(history: &mut History, program: &str, error: FsError, subject: &str) -> StatusCode {
+    let _ = history.write(html! {<>{error_message(program, error, subject)}</>});
+    error.status()
+}
+
+/// Splits arguments into single-letter flags and operands; `--` ends flag parsing. Any flag not
+/// in `allowed` is returned as the error.
+fn parse_flags<'a>(args: &'a [String], allowed: &str) -> Result<(String, Vec<&'a str>), char> {
+    let mut flags = String::new();
+    let mut operands = Vec::new();
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix('-') {
+            Some("-") => {
+                operands.extend(args.map(String::as_str));
+                break;
+            }
+            Some(arg_flags) if !arg_flags.is_empty() => {
+                for flag in arg_flags.chars() {
+                    if !allowed.contains(flag) {
+                        return Err(flag);
+                    }
+                    flags.push(flag);
+                }
+            }
+            _ => operands.push(arg.as_str()),
+        }
+    }
+
+    Ok((flags, operands))
+}
+
+/// Pulls an option that takes a value, such as `-L 2`, `-L2` or `-aL 2`, out of `args`,
+/// returning its value and the remaining arguments for [`parse_flags`]. The last occurrence
+/// wins. An option given without a value is returned as the error.
+fn take_value(args: &[String], option: char) -> Result<(Option<String>, Vec<String>), char> {
+    let mut value = None;
+    let mut rest = vec![args[0].clone()];
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            rest.push(arg.clone());
+            rest.extend(args.cloned());
+            break;
+        }
+
+        let position = arg
+            .strip_prefix('-')
+            .filter(|flags| !flags.starts_with('-'))
+            .and_then(|flags| flags.find(option));
+        let Some(position) = position else {
+            rest.push(arg.clone());
+            continue;
+        };
+        // any flags before the option stay behind, and anything after it is the value
+        let (flags, attached) = arg.split_at(position + 1);
+        if flags.len() > 1 {
+            rest.push(flags.into());
+        }
+        value = Some(match &attached[option.len_utf8()..] {
+            "" => args.next().ok_or(option)?.clone(),
+            attached => attached.into(),
+        });
+    }
+
+    Ok((value, rest))
+}
+
+fn invalid_option(history: &mut History, program: &str, flag: char) -> StatusCode {
+    let _ = history.write(html! {<>{format!("leash: {program}: invalid option -- '{flag}'")}</>});
+    StatusCode(2)
+}
+
+pub(crate) fn join_lines(lines: impl IntoIterator<Item = Html>) -> Html {
+    html! {
+        <>
+            {
+                for lines.into_iter().enumerate().map(|(i, line)| html! {
+                    <>
+                        if i > 0 {
+                            <br />
+                        }
+                        {line}
+                    </>
+                })
+            }
+        </>
+    }
+}
+
+/// Writes `lines` as the program's output; nothing at all is written if there are none.
+fn write_lines(history: &mut History, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    let _ = history.write(join_lines(lines.iter().map(|line| html! {<>{line}</>})));
+}
+
 fn fastfetch(
     _args: &[String],
     _cwd: &mut PathBuf,
     _fs_tree: &mut FsTree,
     history: &mut History,
 ) -> StatusCode {
-    history.write(html! {
+    let _ = history.write(html! {
         <><Fastfetch /></>
     });
     StatusCode(0)
 }
 
+/// Formats a timestamp in milliseconds the way `ls -l` does, e.g. `Oct 18 09:41`.
+fn format_time(millis: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let time = DateTime::local(millis);
+    format!(
+        "{} {:>2} {:02}:{:02}",
+        MONTHS[time.month as usize - 1],
+        time.day,
+        time.hour,
+        time.minute
+    )
+}
+
+/// Formats a timestamp in milliseconds as ISO 8601 in UTC, e.g. `2026-10-18T09:41:00.000Z`.
+fn format_iso(millis: u64) -> String {
+    let time = DateTime::utc(millis);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
+
+/// An entry name as listed by `ls`, with the target appended for symbolic links.
+fn display_name(fs_tree: &FsTree, name: &str, index: FsIndex) -> String {
+    match fs_tree.read_link(index) {
+        Ok(target) => format!("{name} -> {target}"),
+        Err(_) => name.into(),
+    }
+}
+
+fn long_listing(fs_tree: &FsTree, name: &str, index: FsIndex) -> Result<String, FsError> {
+    let metadata = fs_tree.metadata(index)?;
+    Ok(format!(
+        "{} {:>2} {} {:>6} {} {}",
+        metadata.mode_string(),
+        metadata.links(),
+        metadata.owner(),
+        metadata.size(),
+        format_time(metadata.modified()),
+        display_name(fs_tree, name, index)
+    ))
+}
+
 fn ls(
     args: &[String],
     cwd: &mut PathBuf,
     fs_tree: &mut FsTree,
     history: &mut History,
 ) -> StatusCode {
-    if history
-        .write(html! {
-            <>
-                {
-                    for fs_tree
-                        .iter_dir(fs_tree.lookup_path(cwd).unwrap())
-                        .unwrap()
-                        .map(|entry| Reverse(entry.name()))
-                        .collect::<BinaryHeap<_>>()
-                        .into_iter_sorted()
-                        .map(|r| html! {<span>{format!("{} ", r.0)}</span>})
+    let (flags, mut targets) = match parse_flags(args, "l") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "ls", flag),
+    };
+    let long = flags.contains('l');
+    if targets.is_empty() {
+        targets.push(".");
+    }
+
+    let mut status = StatusCode(0);
+    let mut output = Vec::new();
+    for &target in &targets {
+        let listing = resolve_path(target, cwd, fs_tree).and_then(|(index, _)| {
+            if fs_tree.is_directory(index)? {
+                let mut entries = fs_tree
+                    .iter_dir(index)?
+                    .map(|entry| (entry.name(), entry.index()))
+                    .collect::<Vec<_>>();
+                entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                Ok(entries)
+            } else {
+                let (index, _) = resolve_path_nofollow(target, cwd, fs_tree)?;
+                Ok(vec![(target.into(), index)])
+            }
+        });
+
+        let rendered = listing.and_then(|entries| {
+            Ok(if long {
+                join_lines(
+                    entries
+                        .iter()
+                        .map(|(name, index)| long_listing(fs_tree, name, *index))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .into_iter()
+                        .map(|line| html! {<span class={classes!("whitespace-pre")}>{line}</span>}),
+                )
+            } else {
+                html! {
+                    {
+                        for entries.iter().map(|(name, index)| html! {
+                            <span>{format!("{} ", display_name(fs_tree, name, *index))}</span>
+                        })
+                    }
                 }
-            </>
-        })
-        .is_ok()
-    {
-        StatusCode(0)
-    } else {
-        // TODO statuses
-        StatusCode(1)
+            })
+        });
+
+        match rendered {
+            Ok(rendered) => output.push(html! {
+                <>
+                    if targets.len() > 1 {
+                        {format!("{target}:")}
+                        <br />
+                    }
+                    {rendered}
+                </>
+            }),
+            Err(error) => {
+                output.push(html! {<>{error_message("ls", error, target)}</>});
+                status = error.status();
+            }
+        }
     }
+
+    let _ = history.write(join_lines(output));
+    status
 }
 
 fn cd(
     args: &[String],
     cwd: &mut PathBuf,
     fs_tree: &mut FsTree,
-    _history: &mut History,
+    history: &mut History,
+) -> StatusCode {
+    let target = match args.get(1).map(String::as_str) {
+        None => env::var("HOME").unwrap_or_else(|| HOME.into()),
+        Some("-") => match env::var("OLDPWD") {
+            Some(oldpwd) => oldpwd,
+            None => {
+                let _ = history.write(html! {<>{"leash: cd: OLDPWD not set"}</>});
+                return StatusCode(1);
+            }
+        },
+        Some(path) => path.into(),
+    };
+
+    let (index, path) = match resolve_path(&target, cwd, fs_tree) {
+        Ok(resolved) => resolved,
+        Err(error) => return report_error(history, "cd", error, &target),
+    };
+
+    match fs_tree.is_directory(index) {
+        Ok(true) => {
+            if args.get(1).is_some_and(|arg| arg == "-") {
+                let _ = history.write(html! {<>{display_path(&path)}</>});
+            }
+            env::set_var("OLDPWD", &cwd.to_string_lossy());
+            *cwd = path;
+            StatusCode(0)
+        }
+        Ok(false) => report_error(history, "cd", FsError::NotADirectory, &target),
+        Err(error) => report_error(history, "cd", error, &target),
+    }
+}
+
+fn move_path(
+    fs_tree: &mut FsTree,
+    cwd: &Path,
+    source: &str,
+    target_dir: Option<FsIndex>,
+    target: &str,
+) -> Result<(), FsError> {
+    let (old_parent, old_name) = resolve_parent(source, cwd, fs_tree)?;
+    let (new_parent, new_name) = match target_dir {
+        Some(dir) => (dir, old_name.clone()),
+        None => resolve_parent(target, cwd, fs_tree)?,
+    };
+
+    fs_tree.move_entry(&old_name, old_parent, &new_name, new_parent)
+}
+
+fn mv(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    if args.len() < 3 {
+        let _ = history.write(html! {<>{"usage: mv source ... target"}</>});
+        return StatusCode(1);
+    }
+
+    let (sources, target) = args[1..].split_at(args.len() - 2);
+    let target = &target[0];
+    let target_dir = resolve_path(target, cwd, fs_tree)
+        .ok()
+        .map(|(index, _)| index)
+        .filter(|&index| fs_tree.is_directory(index) == Ok(true));
+
+    if sources.len() > 1 && target_dir.is_none() {
+        return report_error(history, "mv", FsError::NotADirectory, target);
+    }
+
+    let mut errors = Vec::new();
+    let mut status = StatusCode(0);
+    for source in sources {
+        let result = move_path(fs_tree, cwd, source, target_dir, target);
+
+        if let Err(error) = result {
+            errors.push(error_message("mv", error, source));
+            status = error.status();
+        }
+    }
+
+    write_lines(history, &errors);
+    status
+}
+
+fn ln(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "s") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "ln", flag),
+    };
+    let (target, link) = match operands[..] {
+        [target] => (target, None),
+        [target, link] => (target, Some(link)),
+        _ => {
+            let _ = history.write(html! {<>{"usage: ln [-s] target [link_name]"}</>});
+            return StatusCode(1);
+        }
+    };
+
+    // like `ln` on Linux, a symbolic link named as the target of a hard link is linked itself
+    let linked = match flags.contains('s') {
+        true => None,
+        false => match resolve_path_nofollow(target, cwd, fs_tree).and_then(|(index, _)| {
+            match fs_tree.is_directory(index)? {
+                true => Err(FsError::DirectoryHardLink),
+                false => Ok(index),
+            }
+        }) {
+            Ok(index) => Some(index),
+            Err(error) => return report_error(history, "ln", error, target),
+        },
+    };
+
+    let target_name = Path::new(target)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(target);
+    let placement = match link {
+        Some(link) => match resolve_path(link, cwd, fs_tree) {
+            Ok((index, _)) if fs_tree.is_directory(index) == Ok(true) => {
+                Ok((index, target_name.into()))
+            }
+            _ => resolve_parent(link, cwd, fs_tree),
+        },
+        None => resolve_path(".", cwd, fs_tree).map(|(index, _)| (index, target_name.into())),
+    };
+
+    let result = placement.and_then(|(parent, name)| match linked {
+        Some(index) => fs_tree.create_hard_link(&name, parent, index),
+        None => fs_tree.create_symlink(&name, parent, target).map(|_| ()),
+    });
+    match result {
+        Ok(()) => StatusCode(0),
+        Err(error) => report_error(history, "ln", error, link.unwrap_or(target)),
+    }
+}
+
+fn readlink(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (canonicalize, paths) = match args.get(1).map(String::as_str) {
+        Some("-f") => (true, &args[2..]),
+        _ => (false, &args[1..]),
+    };
+
+    let mut status = StatusCode(0);
+    let mut lines = Vec::new();
+    for path in paths {
+        let result = if canonicalize {
+            resolve_path(path, cwd, fs_tree).map(|(_, path)| path.to_string_lossy().into())
+        } else {
+            resolve_path_nofollow(path, cwd, fs_tree)
+                .and_then(|(index, _)| fs_tree.read_link(index))
+        };
+
+        match result {
+            Ok(target) => lines.push(target.to_string()),
+            Err(error) => {
+                lines.push(error_message("readlink", error, path));
+                status = error.status();
+            }
+        }
+    }
+
+    write_lines(history, &lines);
+    status
+}
+
+fn realpath(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let mut status = StatusCode(0);
+    let mut lines = Vec::new();
+    for path in &args[1..] {
+        match resolve_path(path, cwd, fs_tree) {
+            Ok((_, canonical)) => lines.push(canonical.to_string_lossy().into()),
+            Err(error) => {
+                lines.push(error_message("realpath", error, path));
+                status = error.status();
+            }
+        }
+    }
+
+    write_lines(history, &lines);
+    status
+}
+
+/// Applies a `chmod` mode, either octal (`755`) or symbolic (`u+x,go-w`), to `mode`.
+fn parse_mode(spec: &str, mode: u16) -> Option<u16> {
+    if spec.chars().all(|c| c.is_digit(8)) {
+        return u16::from_str_radix(spec, 8)
+            .ok()
+            .filter(|&mode| mode <= 0o7777);
+    }
+
+    let mut mode = mode;
+    for clause in spec.split(',') {
+        let op_start = clause.find(['+', '-', '='])?;
+        let (who, mut rest) = clause.split_at(op_start);
+
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who_mask == 0 {
+            who_mask = 0o777;
+        }
+
+        while let Some(op) = rest.chars().next() {
+            let perms_end = rest[1..]
+                .find(['+', '-', '='])
+                .map_or(rest.len(), |i| i + 1);
+            let mut bits = 0;
+            for c in rest[1..perms_end].chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => return None,
+                };
+            }
+            bits &= who_mask;
+
+            match op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                '=' => mode = (mode & !who_mask) | bits,
+                _ => return None,
+            }
+            rest = &rest[perms_end..];
+        }
+    }
+
+    Some(mode)
+}
+
+fn chmod(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let [spec, paths @ ..] = &args[1..] else {
+        let _ = history.write(html! {<>{"usage: chmod mode file ..."}</>});
+        return StatusCode(1);
+    };
+    if paths.is_empty() {
+        let _ = history.write(html! {<>{"usage: chmod mode file ..."}</>});
+        return StatusCode(1);
+    }
+
+    let mut status = StatusCode(0);
+    let mut errors = Vec::new();
+    for path in paths {
+        let result = resolve_path(path, cwd, fs_tree).and_then(|(index, _)| {
+            let mode = fs_tree.metadata(index)?.mode();
+            match parse_mode(spec, mode) {
+                Some(mode) => fs_tree.set_mode(index, mode),
+                None => Err(FsError::InvalidMode),
+            }
+        });
+
+        if let Err(error) = result {
+            errors.push(error_message("chmod", error, path));
+            status = error.status();
+        }
+    }
+
+    write_lines(history, &errors);
+    status
+}
+
+fn stat(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (follow, paths) = match args.get(1).map(String::as_str) {
+        Some("-L") => (true, &args[2..]),
+        _ => (false, &args[1..]),
+    };
+
+    let mut status = StatusCode(0);
+    let mut lines = Vec::new();
+    for path in paths {
+        let resolved = if follow {
+            resolve_path(path, cwd, fs_tree)
+        } else {
+            resolve_path_nofollow(path, cwd, fs_tree)
+        };
+
+        match resolved.and_then(|(index, _)| Ok((index, fs_tree.metadata(index)?))) {
+            Ok((index, metadata)) => {
+                lines.extend([
+                    format!("  File: {}", display_name(fs_tree, path, index)),
+                    format!(
+                        "  Size: {:<10} {}",
+                        metadata.size(),
+                        metadata.file_type().description()
+                    ),
+                    format!(
+                        " Inode: {:<10} Links: {}",
+                        metadata.inode(),
+                        metadata.links()
+                    ),
+                    format!(
+                        "Access: ({:04o}/{})  Owner: {}",
+                        metadata.mode(),
+                        metadata.mode_string(),
+                        metadata.owner()
+                    ),
+                    format!("Modify: {}", format_iso(metadata.modified())),
+                    format!(" Birth: {}", format_iso(metadata.created())),
+                ]);
+            }
+            Err(error) => {
+                lines.push(error_message("stat", error, path));
+                status = error.status();
+            }
+        }
+    }
+
+    let _ =
+        history.write(join_lines(lines.into_iter().map(
+            |line| html! {<span class={classes!("whitespace-pre")}>{line}</span>},
+        )));
+    status
+}
+
+fn rm(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, paths) = match parse_flags(args, "rRf") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "rm", flag),
+    };
+    let recursive = flags.contains(['r', 'R']);
+    let force = flags.contains('f');
+
+    if paths.is_empty() && !force {
+        let _ = history.write(html! {<>{"usage: rm [-rf] file ..."}</>});
+        return StatusCode(1);
+    }
+
+    let mut status = StatusCode(0);
+    let mut errors = Vec::new();
+    for path in paths {
+        let result = resolve_path_nofollow(path, cwd, fs_tree).and_then(|(index, _)| {
+            let is_directory = fs_tree.is_directory(index)?;
+            let (parent, name) = resolve_parent(path, cwd, fs_tree)?;
+            match (is_directory, recursive) {
+                (true, true) => fs_tree.delete_recursive(&name, parent),
+                (true, false) => Err(FsError::IsADirectory),
+                (false, _) => fs_tree.delete(&name, parent),
+            }
+        });
+
+        match result {
+            Ok(()) | Err(FsError::NotFound) if force => {}
+            Ok(()) => {}
+            Err(error) => {
+                errors.push(error_message("rm", error, path));
+                status = error.status();
+            }
+        }
+    }
+
+    write_lines(history, &errors);
+    status
+}
+
+/// Prints the contents of each file in turn. Images are shown inline; other binary files are
+/// refused.
+fn cat(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
 ) -> StatusCode {
     if args.len() < 2 {
-        unimplemented!()
+        let _ = history.write(html! {<>{"usage: cat file ..."}</>});
+        return StatusCode(1);
     }
 
-    let target_path = if args[1].starts_with('/') {
-        PathBuf::from(&args[1])
-    } else {
-        let mut target_path = cwd.clone();
-        target_path.push(&args[1]);
-        target_path
+    let mut status = StatusCode(0);
+    let mut lines = Vec::new();
+    for path in &args[1..] {
+        let contents = resolve_path(path, cwd, fs_tree)
+            .and_then(|(index, _)| fs_tree.contents(index))
+            .and_then(|contents| match contents {
+                Contents::Text(text) => {
+                    let text = text.strip_suffix('\n').unwrap_or(&text).to_owned();
+                    Ok(html! {<span class={classes!("whitespace-pre-wrap")}>{text}</span>})
+                }
+                Contents::Binary(_) if contents.image_type().is_some() => Ok(html! {
+                    <img src={contents.data_url()} alt={path.clone()} class={classes!("max-h-80")} />
+                }),
+                Contents::Binary(_) => Err(FsError::BinaryFile),
+            });
+
+        match contents {
+            Ok(contents) => lines.push(contents),
+            Err(error) => {
+                lines.push(html! {<>{error_message("cat", error, path)}</>});
+                status = error.status();
+            }
+        }
+    }
+
+    let _ = history.write(join_lines(lines));
+    status
+}
+
+fn rmdir(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    if args.len() < 2 {
+        let _ = history.write(html! {<>{"usage: rmdir directory ..."}</>});
+        return StatusCode(1);
+    }
+
+    let mut status = StatusCode(0);
+    let mut errors = Vec::new();
+    for path in &args[1..] {
+        let result = resolve_path_nofollow(path, cwd, fs_tree).and_then(|(index, _)| {
+            if !fs_tree.is_directory(index)? {
+                return Err(FsError::NotADirectory);
+            }
+            let (parent, name) = resolve_parent(path, cwd, fs_tree)?;
+            fs_tree.delete(&name, parent)
+        });
+
+        if let Err(error) = result {
+            errors.push(error_message("rmdir", error, path));
+            status = error.status();
+        }
+    }
+
+    write_lines(history, &errors);
+    status
+}
+
+/// Offers files to the visitor as browser downloads. Directories are refused unless `-r` is
+/// given, in which case they are downloaded as tar archives.
+fn download(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "r") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "download", flag),
     };
+    if operands.is_empty() {
+        let _ = history.write(html! {<>{"usage: download [-r] path ..."}</>});
+        return StatusCode(2);
+    }
 
-    match fs_tree.lookup_path(&target_path) {
-        Some(index) => {
-            if fs_tree.is_directory(index).unwrap() {
-                *cwd = canonicalize(&target_path, fs_tree).unwrap();
-                StatusCode(0)
-            } else {
-                unimplemented!()
+    let mut status = StatusCode(0);
+    let mut errors = Vec::new();
+    for path in operands {
+        let (index, canonical) = match resolve_path(path, cwd, fs_tree) {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                errors.push(error_message("download", error, path));
+                status = error.status();
+                continue;
+            }
+        };
+        let name = canonical
+            .file_name()
+            .map_or("root".into(), |name| name.to_string_lossy().into_owned());
+
+        let prepared = match fs_tree.is_directory(index) {
+            Ok(true) if flags.contains('r') => crate::tar::archive(fs_tree, cwd, path)
+                .map_err(TarError::from)
+                .and_then(|(entries, _)| {
+                    let bytes = crate::tar::write(&entries)?;
+                    Ok((format!("{name}.tar"), bytes, "application/x-tar"))
+                }),
+            Ok(true) => Err(FsError::IsADirectory.into()),
+            Ok(false) => fs_tree
+                .contents(index)
+                .map(|contents| (name, contents.bytes().to_vec(), contents.mime_type()))
+                .map_err(TarError::from),
+            Err(error) => Err(error.into()),
+        };
+
+        match prepared {
+            Ok((name, bytes, mime)) => {
+                if let Err(error) = transfer::download(&name, &bytes, mime) {
+                    errors.push(format!("leash: download: {error}"));
+                    status = StatusCode(1);
+                }
+            }
+            Err(TarError::Filesystem(error)) => {
+                errors.push(error_message("download", error, path));
+                status = error.status();
+            }
+            Err(error) => {
+                errors.push(format!("leash: download: {error}"));
+                status = StatusCode(1);
             }
         }
-        None => unimplemented!(),
     }
+
+    write_lines(history, &errors);
+    status
+}
+
+/// Asks the visitor for files to save in the working directory. Files can also be dropped onto
+/// the page.
+fn upload(
+    args: &[String],
+    cwd: &mut PathBuf,
+    _fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    if args.len() > 1 {
+        let _ = history.write(html! {<>{"usage: upload"}</>});
+        return StatusCode(2);
+    }
+
+    let _ = history.write(html! {<>{"upload: choose files to upload"}</>});
+    transfer::request_upload(Action::Upload { cwd: cwd.clone() }, history);
+    StatusCode(0)
+}
+
+/// Throws away the visitor's changes, leaving only the site content. A bare `reset` also
+/// reinitializes the terminal; `reset --upper` touches nothing but the filesystem.
+fn reset(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let upper_only = match &args[1..] {
+        [] => false,
+        [flag] if flag == "--upper" => true,
+        _ => {
+            let _ = history.write(html! {<>{"usage: reset [--upper]"}</>});
+            return StatusCode(2);
+        }
+    };
+
+    fs_tree.discard_upper();
+    if let Err(error) = persist::discard(false)
+        && !matches!(error, PersistError::Unavailable)
+    {
+        let _ = history.write(html! {<>{format!("leash: reset: {error}")}</>});
+        return StatusCode(1);
+    }
+
+    if upper_only {
+        let _ = history.write(html! {<>{"visitor changes discarded"}</>});
+    } else {
+        *cwd = PathBuf::from(HOME);
+        history.clear();
+    }
+    StatusCode(0)
+}
+
+/// Lists the layers that make up the filesystem.
+fn mount(
+    args: &[String],
+    _cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    if args.len() > 1 {
+        let _ = history.write(html! {<>{"leash: mount: mounting is not supported"}</>});
+        return StatusCode(32);
+    }
+
+    let upper = match fs_tree.upper_layer() {
+        Ok(upper) => upper,
+        Err(error) => return report_error(history, "mount", error, "/"),
+    };
+
+    let mut lines = Vec::new();
+    if let Some(lower) = fs_tree.lower() {
+        lines.push(format!(
+            "content on / type lower (ro, {} nodes)",
+            lower.node_count()
+        ));
+    }
+    lines.push(format!(
+        "visitor on / type upper (rw, {} entries, {} whiteouts)",
+        upper.entries(),
+        upper.whiteouts()
+    ));
+    if fs_tree.lower().is_some() {
+        lines.push(String::from(
+            "overlay on / type overlay (rw, lowerdir=content, upperdir=visitor)",
+        ));
+    }
+
+    write_lines(history, &lines);
+    StatusCode(0)
+}
+
+/// Formats a size in bytes the way `-h` does in coreutils, rounding up to one significant
+/// decimal: `512`, `1.5K`, `12K`, `2.0M`.
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.1}{}", (size * 10.0).ceil() / 10.0, UNITS[unit])
+    } else {
+        format!("{}{}", size.ceil(), UNITS[unit])
+    }
+}
+
+/// Reports how much of each quota the tree uses. `-h` shows byte counts in human-readable units.
+fn df(
+    args: &[String],
+    _cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "h") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "df", flag),
+    };
+    if !operands.is_empty() {
+        let _ = history.write(html! {<>{"usage: df [-h]"}</>});
+        return StatusCode(2);
+    }
+
+    let bytes = |size: usize| match flags.contains('h') {
+        true => human_size(size),
+        false => size.to_string(),
+    };
+    let quota = fs_tree.quota();
+    let usage = fs_tree.usage();
+    let rows = [
+        (
+            "nodes",
+            usage.nodes.to_string(),
+            quota.nodes.to_string(),
+            usage.nodes,
+            quota.nodes,
+        ),
+        (
+            "bytes",
+            bytes(usage.bytes),
+            bytes(quota.bytes),
+            usage.bytes,
+            quota.bytes,
+        ),
+        (
+            "file size",
+            bytes(usage.file_size),
+            bytes(quota.file_size),
+            usage.file_size,
+            quota.file_size,
+        ),
+        (
+            "depth",
+            usage.depth.to_string(),
+            quota.depth.to_string(),
+            usage.depth,
+            quota.depth,
+        ),
+    ];
+
+    let lines = [format!(
+        "{:<10}{:>9}{:>9}{:>6}",
+        "quota", "used", "limit", "use%"
+    )]
+    .into_iter()
+    .chain(
+        rows.into_iter()
+            .map(|(name, used, limit, used_count, limit_count)| {
+                let percent = (used_count * 100).div_ceil(limit_count.max(1));
+                format!("{name:<10}{used:>9}{limit:>9}{:>6}", format!("{percent}%"))
+            }),
+    );
+    let _ = history.write(join_lines(
+        lines.map(|line| html! {<span class={classes!("whitespace-pre")}>{line}</span>}),
+    ));
+    StatusCode(0)
+}
+
+/// Checks the tree's invariants, repairing them with `-y`. Exit statuses follow `fsck(8)`: 0
+/// for a clean tree, 1 when problems were corrected and 4 when they were left alone.
+fn fsck(
+    args: &[String],
+    _cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let repair = match &args[1..] {
+        [] => false,
+        [flag] if flag == "-y" => true,
+        _ => {
+            let _ = history.write(html! {<>{"usage: fsck [-y]"}</>});
+            return StatusCode(16);
+        }
+    };
+
+    let violations = if repair {
+        match fs_tree.repair() {
+            Ok(violations) => violations,
+            Err(error) => return report_error(history, "fsck", error, "/"),
+        }
+    } else {
+        fs_tree.check()
+    };
+
+    let mut lines = violations
+        .iter()
+        .map(|violation| format!("fsck: {violation}"))
+        .collect::<Vec<_>>();
+    let status = match (violations.len(), repair) {
+        (0, _) => {
+            lines.push(String::from("fsck: filesystem is clean"));
+            StatusCode(0)
+        }
+        (count, true) => {
+            lines.push(format!("fsck: repaired {count} problem(s)"));
+            StatusCode(1)
+        }
+        (count, false) => {
+            lines.push(format!(
+                "fsck: found {count} problem(s); run `fsck -y` to repair them"
+            ));
+            StatusCode(4)
+        }
+    };
+
+    write_lines(history, &lines);
+    status
 }
 
 fn help(
@@ -110,6 +1082,10 @@ fn help(
                         .into_iter_sorted()
                         .map(|r| html! {<span>{format!("{} ", r.0)}</span>})
                 }
+                <br />
+                <span>
+                    {"/dev/clipboard reads back what was last copied, pasted or written on this page, not the system clipboard"}
+                </span>
             </>
         })
         .is_ok()
@@ -149,28 +1125,78 @@ fn clear(
     StatusCode(0)
 }
 
-pub static EXECUTE_FILE: Program = |
-    args: &[String],
-    cwd: &mut PathBuf,
-    fs_tree: &mut FsTree,
-    history: &mut History,
-| {
-    match if args[0].starts_with('/') {
-        fs_tree.lookup_path(&Path::new(&args[0]))
-    } else {
-        let mut target_path = cwd.clone();
-        target_path.push(&Path::new(&args[0]));
-        fs_tree.lookup_path(&target_path)
-    } {
-        Some(index) => {
-            fs_tree.execute(index).map_or_else(|_| unimplemented!(), |(output, result)| {
-                history.write(output).unwrap();
+pub static EXECUTE_FILE: Program =
+    |args: &[String], cwd: &mut PathBuf, fs_tree: &mut FsTree, history: &mut History| {
+        match resolve_path(&args[0], cwd, fs_tree).and_then(|(index, _)| fs_tree.execute(index)) {
+            Ok((output, result)) => {
+                let _ = history.write(output);
                 result
-            })
-        },
-        None => {
-            history.write(html! {<>{format!("leash: no such file or directory: {}", args[0])}</>});
-            StatusCode(1)
+            }
+            Err(error) => {
+                let _ = history.write(html! {<>{format!("leash: {error}: {}", args[0])}</>});
+                match error {
+                    FsError::PermissionDenied | FsError::BinaryFile => StatusCode(126),
+                    error => error.status(),
+                }
+            }
         }
+    };
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+
+    use super::{format_iso, format_time};
+    use crate::{ExecutionRecord, History, StatusCode, fs::FsTree, render_text, submit_command};
+
+    /// Runs `command` as the shell would, returning its status and its output as text.
+    pub(crate) fn run(
+        command: &str,
+        cwd: &mut PathBuf,
+        fs_tree: &Rc<RefCell<FsTree>>,
+    ) -> (StatusCode, String) {
+        let mut history = History(vec![ExecutionRecord::new(
+            StatusCode(0),
+            "~",
+            command,
+            None,
+        )]);
+        let status = submit_command(command, cwd, Rc::clone(fs_tree), &mut history);
+        let output = history.0[0].output().map(render_text).unwrap_or_default();
+        (status, output)
     }
-};
+
+    #[test]
+    fn formats_times() {
+        // native builds show local times in UTC
+        assert_eq!(format_time(1_700_000_000_123), "Nov 14 22:13");
+        assert_eq!(format_time(0), "Jan  1 00:00");
+        assert_eq!(format_iso(1_700_000_000_123), "2023-11-14T22:13:20.123Z");
+        assert_eq!(format_iso(0), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn lists_and_stats_with_timestamps() {
+        let fs_tree = Rc::new(RefCell::new(FsTree::new()));
+        let mut cwd = PathBuf::from("/");
+        assert_eq!(
+            run("echo hello > notes", &mut cwd, &fs_tree).0,
+            StatusCode(0)
+        );
+
+        let (status, listing) = run("ls -l notes", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(0));
+        assert!(
+            listing.starts_with("-rw-r--r--  1 user      6 "),
+            "{listing}"
+        );
+        assert!(listing.ends_with(" notes"), "{listing}");
+
+        let (status, stats) = run("stat notes", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(0));
+        assert!(stats.contains("  Size: 6 "), "{stats}");
+        assert!(stats.contains("Modify: 20"), "{stats}");
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashSet;
+
+use unix_path::{Path, PathBuf};
+use yew::{classes, html};
+
+use super::{error_message, human_size, invalid_option, join_lines, parse_flags, take_value};
+use crate::{
+    History, StatusCode,
+    fs::{FsIndex, FsTree},
+    resolve_path_nofollow,
+};
+
+struct Options {
+    /// `-h`: sizes in human-readable units rather than kibibytes.
+    human: bool,
+    /// `-d`, or `-s` for 0: how many levels of directories below each path to list.
+    max_depth: usize,
+}
+
+impl Options {
+    fn format(&self, bytes: usize) -> String {
+        match self.human {
+            true => human_size(bytes),
+            false => bytes.div_ceil(1024).to_string(),
+        }
+    }
+}
+
+/// Adds up the size of everything at and below `index`, without following symbolic links, and
+/// lists each directory no more than [`Options::max_depth`] levels down after its contents.
+/// Nodes already in `seen` count for nothing, so a file with several hard links counts once.
+fn total(
+    fs_tree: &FsTree,
+    path: &Path,
+    index: FsIndex,
+    depth: usize,
+    options: &Options,
+    seen: &mut HashSet<FsIndex>,
+    lines: &mut Vec<String>,
+) -> usize {
+    if !seen.insert(index) {
+        return 0;
+    }
+    let Ok(children) = fs_tree.iter_children(index) else {
+        // only apparent sizes are counted, so directories themselves take no space
+        return fs_tree
+            .metadata(index)
+            .map_or(0, |metadata| metadata.size());
+    };
+    let mut children = children.collect::<Vec<_>>();
+    children.sort_unstable_by_key(|entry| entry.name());
+
+    let size = children
+        .into_iter()
+        .map(|entry| {
+            let path = path.join(&*entry.name());
+            total(
+                fs_tree,
+                &path,
+                entry.index(),
+                depth + 1,
+                options,
+                seen,
+                lines,
+            )
+        })
+        .sum();
+    if depth <= options.max_depth {
+        lines.push(format!("{}\t{}", options.format(size), path.display()));
+    }
+    size
+}
+
+/// Reports the space used by each directory below the given paths, in kibibytes rounded up.
+/// Sizes are apparent sizes, the bytes held by files and link targets. `-h` uses human-readable
+/// units, `-s` shows only a total for each path and `-d` limits how deep directories are listed.
+pub(super) fn du(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (depth, args) = match take_value(args, 'd') {
+        Ok(taken) => taken,
+        Err(flag) => {
+            let _ = history.write(html! {
+                <>{format!("leash: du: option requires an argument -- '{flag}'")}</>
+            });
+            return StatusCode(2);
+        }
+    };
+    let (flags, mut paths) = match parse_flags(&args, "hs") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "du", flag),
+    };
+    let max_depth = match (
+        depth.as_deref().map(str::parse::<usize>),
+        flags.contains('s'),
+    ) {
+        (None, false) => usize::MAX,
+        (None, true) | (Some(Ok(0)), _) => 0,
+        (Some(Ok(depth)), false) => depth,
+        (Some(Ok(depth)), true) => {
+            let _ = history.write(html! {
+                <>{format!("leash: du: summarizing conflicts with -d {depth}")}</>
+            });
+            return StatusCode(2);
+        }
+        (Some(Err(_)), _) => {
+            let _ = history.write(html! {
+                <>{format!("leash: du: invalid maximum depth: {}", depth.unwrap_or_default())}</>
+            });
+            return StatusCode(2);
+        }
+    };
+    let options = Options {
+        human: flags.contains('h'),
+        max_depth,
+    };
+    if paths.is_empty() {
+        paths.push(".");
+    }
+
+    let mut status = StatusCode(0);
+    let mut seen = HashSet::new();
+    let mut lines = Vec::new();
+    for path in paths {
+        match resolve_path_nofollow(path, cwd, fs_tree) {
+            Ok((index, _)) => {
+                let size = total(
+                    fs_tree,
+                    Path::new(path),
+                    index,
+                    0,
+                    &options,
+                    &mut seen,
+                    &mut lines,
+                );
+                // files named directly are listed too
+                if !fs_tree.is_directory(index).unwrap_or(false) {
+                    lines.push(format!("{}\t{path}", options.format(size)));
+                }
+            }
+            Err(error) => {
+                lines.push(error_message("du", error, path));
+                status = error.status();
+            }
+        }
+    }
+
+    let _ =
+        history.write(join_lines(lines.into_iter().map(
+            |line| html! {<span class={classes!("whitespace-pre")}>{line}</span>},
+        )));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+
+    use crate::{StatusCode, fs::FsTree, programs::tests::run};
+
+    /// `p` holds 3600 bytes: 2000 in `a`, 1500 in `sub/b` and 100 in `sub/deep/c`.
+    fn sized() -> Rc<RefCell<FsTree>> {
+        let mut fs_tree = FsTree::new();
+        let p = fs_tree.create_directory("p", fs_tree.root()).unwrap();
+        let sub = fs_tree.create_directory("sub", p).unwrap();
+        let deep = fs_tree.create_directory("deep", sub).unwrap();
+        for (name, parent, size) in [("a", p, 2000), ("b", sub, 1500), ("c", deep, 100)] {
+            let file = fs_tree.create_file(name, parent).unwrap();
+            fs_tree.write(file, "x".repeat(size).as_str()).unwrap();
+        }
+        Rc::new(RefCell::new(fs_tree))
+    }
+
+    #[test]
+    fn totals_each_directory_in_kibibytes() {
+        let fs_tree = sized();
+        let mut cwd = PathBuf::from("/");
+        assert_eq!(
+            run("du p", &mut cwd, &fs_tree),
+            (StatusCode(0), "1\tp/sub/deep\n2\tp/sub\n4\tp".into())
+        );
+        assert_eq!(run("du p/a", &mut cwd, &fs_tree).1, "2\tp/a");
+    }
+
+    #[test]
+    fn limits_how_deep_directories_are_listed() {
+        let fs_tree = sized();
+        let mut cwd = PathBuf::from("/p");
+        assert_eq!(run("du -d 1", &mut cwd, &fs_tree).1, "2\t./sub\n4\t.");
+        // as in GNU du, a directory already counted under an earlier path is skipped
+        assert_eq!(run("du -s . sub", &mut cwd, &fs_tree).1, "4\t.");
+        assert_eq!(run("du -s sub", &mut cwd, &fs_tree).1, "2\tsub");
+        assert_eq!(run("du -d 0", &mut cwd, &fs_tree).1, "4\t.");
+
+        let (status, output) = run("du -s -d 1", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(2));
+        assert_eq!(output, "leash: du: summarizing conflicts with -d 1");
+    }
+
+    #[test]
+    fn counts_hard_links_once() {
+        let fs_tree = sized();
+        let mut cwd = PathBuf::from("/p");
+        assert_eq!(run("ln a sub/again", &mut cwd, &fs_tree).0, StatusCode(0));
+        assert_eq!(run("du -s", &mut cwd, &fs_tree).1, "4\t.");
+    }
+}
@@ -0,0 +1,308 @@
+use unix_path::{Path, PathBuf};
+use yew::{Html, html};
+
+use super::{error_message, join_lines};
+use crate::{
+    ExecutionRecord, History, StatusCode,
+    fs::{FileType, FsError, FsIndex, FsTree},
+    get_program, glob, resolve_path_nofollow,
+};
+
+/// A test applied to every path visited.
+enum Test {
+    Name(String),
+    /// `-iname`, with the pattern already lowercased.
+    IName(String),
+    Type(FileType),
+    Size(Comparison, u64, u64),
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Less,
+    Equal,
+    Greater,
+}
+
+struct Query {
+    tests: Vec<Test>,
+    /// The `-exec` command, with `{}` standing for the path.
+    exec: Option<Vec<String>>,
+    min_depth: usize,
+    max_depth: usize,
+}
+
+/// Parses a `-size` argument such as `+10k`, returning the comparison, the count and the size
+/// of its unit in bytes. Without a suffix the unit is a 512-byte block, as in `find(1)`.
+fn parse_size(arg: &str) -> Option<(Comparison, u64, u64)> {
+    let (comparison, arg) = match arg.split_at_checked(1) {
+        Some(("+", rest)) => (Comparison::Greater, rest),
+        Some(("-", rest)) => (Comparison::Less, rest),
+        _ => (Comparison::Equal, arg),
+    };
+    let (count, unit) = match arg.char_indices().last()? {
+        (i, 'c') => (&arg[..i], 1),
+        (i, 'w') => (&arg[..i], 2),
+        (i, 'b') => (&arg[..i], 512),
+        (i, 'k') => (&arg[..i], 1 << 10),
+        (i, 'M') => (&arg[..i], 1 << 20),
+        (i, 'G') => (&arg[..i], 1 << 30),
+        _ => (arg, 512),
+    };
+
+    Some((comparison, count.parse().ok()?, unit))
+}
+
+/// Splits the arguments into starting points and the query that follows them.
+fn parse_query(args: &[String]) -> Result<(Vec<&str>, Query), String> {
+    let mut args = args[1..].iter().map(String::as_str).peekable();
+    let mut starts = Vec::new();
+    while let Some(start) = args.next_if(|arg| !arg.starts_with('-')) {
+        starts.push(start);
+    }
+    if starts.is_empty() {
+        starts.push(".");
+    }
+
+    let mut query = Query {
+        tests: Vec::new(),
+        exec: None,
+        min_depth: 0,
+        max_depth: usize::MAX,
+    };
+    while let Some(predicate) = args.next() {
+        let missing = || format!("missing argument to `{predicate}'");
+        let invalid = |arg: &str| format!("invalid argument `{arg}' to `{predicate}'");
+
+        match predicate {
+            "-exec" => {
+                let mut command = Vec::new();
+                loop {
+                    match args.next() {
+                        Some(";") => break,
+                        Some(arg) => command.push(arg.to_owned()),
+                        None => return Err(missing()),
+                    }
+                }
+                if command.is_empty() {
+                    return Err(missing());
+                }
+                query.exec = Some(command);
+            }
+            "-name" | "-iname" | "-type" | "-size" | "-maxdepth" | "-mindepth" => {
+                let arg = args.next().ok_or_else(missing)?;
+                match predicate {
+                    "-name" => query.tests.push(Test::Name(arg.into())),
+                    "-iname" => query.tests.push(Test::IName(arg.to_lowercase())),
+                    "-type" => query.tests.push(Test::Type(match arg {
+                        "f" => FileType::File,
+                        "d" => FileType::Directory,
+                        "l" => FileType::Symlink,
+                        "c" => FileType::CharDevice,
+                        _ => return Err(invalid(arg)),
+                    })),
+                    "-size" => {
+                        let (comparison, count, unit) =
+                            parse_size(arg).ok_or_else(|| invalid(arg))?;
+                        query.tests.push(Test::Size(comparison, count, unit));
+                    }
+                    "-maxdepth" => query.max_depth = arg.parse().map_err(|_| invalid(arg))?,
+                    _ => query.min_depth = arg.parse().map_err(|_| invalid(arg))?,
+                }
+            }
+            predicate => return Err(format!("unknown predicate `{predicate}'")),
+        }
+    }
+
+    Ok((starts, query))
+}
+
+impl Query {
+    fn matches(&self, fs_tree: &FsTree, name: &str, index: FsIndex) -> Result<bool, FsError> {
+        let metadata = fs_tree.metadata(index)?;
+        Ok(self.tests.iter().all(|test| match test {
+            Test::Name(pattern) => glob::matches(pattern, name),
+            Test::IName(pattern) => glob::matches(pattern, &name.to_lowercase()),
+            Test::Type(file_type) => metadata.file_type() == *file_type,
+            Test::Size(comparison, count, unit) => {
+                // sizes are rounded up to whole units before comparing
+                let size = (metadata.size() as u64).div_ceil(*unit);
+                match comparison {
+                    Comparison::Less => size < *count,
+                    Comparison::Equal => size == *count,
+                    Comparison::Greater => size > *count,
+                }
+            }
+        }))
+    }
+
+    /// Collects every path below `path` that passes the tests, without following symbolic
+    /// links, in sorted order.
+    fn walk(
+        &self,
+        fs_tree: &FsTree,
+        path: &str,
+        index: FsIndex,
+        depth: usize,
+        found: &mut Vec<String>,
+    ) -> Result<(), FsError> {
+        let name = Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy());
+        if depth >= self.min_depth && self.matches(fs_tree, &name, index)? {
+            found.push(path.into());
+        }
+
+        if depth >= self.max_depth || !fs_tree.is_directory(index)? {
+            return Ok(());
+        }
+
+        let mut entries = fs_tree.iter_children(index)?.collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|entry| entry.name());
+        for entry in entries {
+            let path = match path {
+                path if path.ends_with('/') => format!("{path}{}", entry.name()),
+                path => format!("{path}/{}", entry.name()),
+            };
+            self.walk(fs_tree, &path, entry.index(), depth + 1, found)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the `-exec` command for one path through the program table, returning its output.
+/// The command gets a copy of the working directory, as it would in a child process.
+fn exec(command: &[String], path: &str, cwd: &Path, fs_tree: &mut FsTree) -> Option<Html> {
+    let args = command
+        .iter()
+        .map(|arg| arg.replace("{}", path))
+        .collect::<Vec<_>>();
+
+    let mut cwd = cwd.to_path_buf();
+    let Some(program) = get_program(&args[0], &cwd, fs_tree) else {
+        return Some(html! {<>{format!("leash: find: {}: command not found", args[0])}</>});
+    };
+
+    let mut output = History(vec![ExecutionRecord::new(
+        StatusCode(0),
+        "",
+        &args.join(" "),
+        None,
+    )]);
+    program(&args, &mut cwd, fs_tree, &mut output);
+    output.take_output()
+}
+
+/// Searches directory trees for paths passing every test, printing them or running `-exec`
+/// on each.
+pub(super) fn find(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (starts, query) = match parse_query(args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            let _ = history.write(html! {<>{format!("leash: find: {message}")}</>});
+            return StatusCode(1);
+        }
+    };
+
+    let mut status = StatusCode(0);
+    let mut output = Vec::new();
+    for start in starts {
+        let mut found = Vec::new();
+        let result = resolve_path_nofollow(start, cwd, fs_tree)
+            .and_then(|(index, _)| query.walk(fs_tree, start, index, 0, &mut found));
+        if let Err(error) = result {
+            output.push(html! {<>{error_message("find", error, start)}</>});
+            status = StatusCode(1);
+        }
+
+        match &query.exec {
+            Some(command) => {
+                output.extend(
+                    found
+                        .iter()
+                        .filter_map(|path| exec(command, path, cwd, fs_tree)),
+                );
+            }
+            None => output.extend(found.into_iter().map(|path| html! {<>{path}</>})),
+        }
+    }
+
+    let _ = history.write(join_lines(output));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+
+    use crate::{StatusCode, fs::FsTree, programs::tests::run};
+
+    fn docs() -> Rc<RefCell<FsTree>> {
+        let mut fs_tree = FsTree::new();
+        let docs = fs_tree.create_directory("docs", fs_tree.root()).unwrap();
+        let notes = fs_tree.create_file("notes.md", docs).unwrap();
+        fs_tree.write(notes, "alpha\n").unwrap();
+        let todo = fs_tree.create_file("todo.txt", docs).unwrap();
+        fs_tree.write(todo, "beta\n").unwrap();
+        let old = fs_tree.create_directory("old", docs).unwrap();
+        fs_tree.create_file("notes.md", old).unwrap();
+        Rc::new(RefCell::new(fs_tree))
+    }
+
+    #[test]
+    fn filters_by_name_and_type() {
+        let fs_tree = docs();
+        let mut cwd = PathBuf::from("/");
+        assert_eq!(
+            run("find docs -name '*.md'", &mut cwd, &fs_tree),
+            (StatusCode(0), "docs/notes.md\ndocs/old/notes.md".into())
+        );
+        assert_eq!(
+            run("find /docs -type d", &mut cwd, &fs_tree).1,
+            "/docs\n/docs/old"
+        );
+        assert_eq!(
+            run("find /docs -type f -iname 'TODO*'", &mut cwd, &fs_tree).1,
+            "/docs/todo.txt"
+        );
+    }
+
+    #[test]
+    fn limits_depth() {
+        let fs_tree = docs();
+        let mut cwd = PathBuf::from("/docs");
+        assert_eq!(
+            run("find -maxdepth 1 -type f", &mut cwd, &fs_tree).1,
+            "./notes.md\n./todo.txt"
+        );
+        assert_eq!(
+            run("find . -mindepth 2", &mut cwd, &fs_tree).1,
+            "./old/notes.md"
+        );
+        assert_eq!(run("find . -maxdepth 0", &mut cwd, &fs_tree).1, ".");
+    }
+
+    #[test]
+    fn runs_exec_for_each_path() {
+        let fs_tree = docs();
+        let mut cwd = PathBuf::from("/");
+        let (status, output) = run(
+            r"find /docs -maxdepth 1 -type f -exec cat {} \;",
+            &mut cwd,
+            &fs_tree,
+        );
+        assert_eq!(status, StatusCode(0));
+        assert_eq!(output.lines().collect::<Vec<_>>(), ["alpha", "beta"]);
+
+        let (status, output) = run("find /docs -exec cat {}", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(1));
+        assert_eq!(output, "leash: find: missing argument to `-exec'");
+    }
+}
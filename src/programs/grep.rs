@@ -0,0 +1,256 @@
+use regex::{Regex, RegexBuilder};
+use unix_path::PathBuf;
+use yew::{Html, classes, html};
+
+use super::{error_message, invalid_option, join_lines, parse_flags};
+use crate::{
+    History, StatusCode,
+    fs::{Contents, FileType, FsError, FsIndex, FsTree},
+    resolve_path,
+};
+
+/// Collects the regular files below the directory `path`, in sorted order. Symbolic links and
+/// devices met along the way are skipped rather than followed or read.
+fn walk(
+    fs_tree: &FsTree,
+    path: &str,
+    index: FsIndex,
+    files: &mut Vec<(String, FsIndex)>,
+) -> Result<(), FsError> {
+    let mut entries = fs_tree.iter_children(index)?.collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|entry| entry.name());
+    for entry in entries {
+        let path = match path {
+            "" => entry.name().to_string(),
+            path if path.ends_with('/') => format!("{path}{}", entry.name()),
+            path => format!("{path}/{}", entry.name()),
+        };
+        match fs_tree.metadata(entry.index())?.file_type() {
+            FileType::Directory => walk(fs_tree, &path, entry.index(), files)?,
+            FileType::File => files.push((path, entry.index())),
+            FileType::Symlink | FileType::CharDevice => {}
+        }
+    }
+    Ok(())
+}
+
+/// Renders `line` with every match of `regex` highlighted.
+fn highlight(regex: &Regex, line: &str) -> Html {
+    let mut parts = Vec::new();
+    let mut end = 0;
+    for found in regex.find_iter(line).filter(|found| !found.is_empty()) {
+        parts.push(html! {<>{&line[end..found.start()]}</>});
+        parts.push(html! {
+            <span class={classes!("text-rose-400", "font-bold")}>{found.as_str()}</span>
+        });
+        end = found.end();
+    }
+    parts.push(html! {<>{&line[end..]}</>});
+
+    html! {<span class={classes!("whitespace-pre-wrap")}>{for parts}</span>}
+}
+
+/// Searches files for lines matching a regular expression. `-r` searches directories
+/// recursively, `-i` ignores case, `-n` numbers lines, `-l` lists matching files, `-v` selects
+/// the lines that don't match, and `-c` counts selected lines.
+pub(super) fn grep(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "rinlvc") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "grep", flag),
+    };
+    let recursive = flags.contains('r');
+    let invert = flags.contains('v');
+
+    let (pattern, mut paths) = match operands.split_first() {
+        Some((pattern, paths)) if recursive || !paths.is_empty() => (*pattern, paths.to_vec()),
+        _ => {
+            let _ = history.write(html! {<>{"usage: grep [-rinlvc] pattern file ..."}</>});
+            return StatusCode(2);
+        }
+    };
+    // with no files given, `-r` searches the working directory, naming files relative to it
+    let searching_cwd = paths.is_empty();
+    if searching_cwd {
+        paths.push(".");
+    }
+
+    let regex = match RegexBuilder::new(pattern)
+        .case_insensitive(flags.contains('i'))
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(_) => {
+            let _ = history.write(
+                html! {<>{format!("leash: grep: invalid regular expression: {pattern}")}</>},
+            );
+            return StatusCode(2);
+        }
+    };
+
+    let mut output = Vec::new();
+    let mut errors = false;
+    let mut files = Vec::new();
+    for path in &paths {
+        let result = resolve_path(path, cwd, fs_tree).and_then(|(index, _)| {
+            match fs_tree.is_directory(index)? {
+                true if recursive => {
+                    let prefix = if searching_cwd { "" } else { path };
+                    walk(fs_tree, prefix, index, &mut files)
+                }
+                true => Err(FsError::IsADirectory),
+                false => {
+                    files.push((path.to_string(), index));
+                    Ok(())
+                }
+            }
+        });
+        if let Err(error) = result {
+            output.push(html! {<>{error_message("grep", error, path)}</>});
+            errors = true;
+        }
+    }
+
+    let show_names = recursive || paths.len() > 1;
+    let mut selected_any = false;
+    for (path, index) in files {
+        let contents = match fs_tree.contents(index) {
+            Ok(contents) => contents,
+            Err(error) => {
+                output.push(html! {<>{error_message("grep", error, &path)}</>});
+                errors = true;
+                continue;
+            }
+        };
+        let (text, binary) = match &contents {
+            Contents::Text(text) => (text.to_string(), false),
+            Contents::Binary(bytes) => (String::from_utf8_lossy(bytes).into_owned(), true),
+        };
+
+        let selected = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| regex.is_match(line) != invert)
+            .collect::<Vec<_>>();
+        selected_any |= !selected.is_empty();
+
+        let prefix = |number: Option<usize>| {
+            html! {
+                <>
+                    if show_names {
+                        <span class={classes!("text-purple-400")}>{&path}</span>{":"}
+                    }
+                    if let Some(number) = number {
+                        <span class={classes!("text-green-300")}>{number + 1}</span>{":"}
+                    }
+                </>
+            }
+        };
+
+        if flags.contains('c') {
+            output.push(html! {<>{prefix(None)}{selected.len()}</>});
+        } else if flags.contains('l') {
+            if !selected.is_empty() {
+                output.push(html! {<>{&path}</>});
+            }
+        } else if binary {
+            // matching lines of a binary file would only print garbage
+            if !selected.is_empty() {
+                output.push(html! {<>{format!("Binary file {path} matches")}</>});
+            }
+        } else {
+            let numbered = flags.contains('n');
+            output.extend(selected.into_iter().map(|(number, line)| {
+                let line = match invert {
+                    true => html! {<span class={classes!("whitespace-pre-wrap")}>{line}</span>},
+                    false => highlight(&regex, line),
+                };
+                html! {<>{prefix(numbered.then_some(number))}{line}</>}
+            }));
+        }
+    }
+
+    if !output.is_empty() {
+        let _ = history.write(join_lines(output));
+    }
+    match (errors, selected_any) {
+        (true, _) => StatusCode(2),
+        (false, true) => StatusCode(0),
+        (false, false) => StatusCode(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+
+    use crate::{
+        StatusCode,
+        fs::{Contents, FsTree},
+        programs::tests::run,
+    };
+
+    fn docs() -> Rc<RefCell<FsTree>> {
+        let mut fs_tree = FsTree::new();
+        let docs = fs_tree.create_directory("docs", fs_tree.root()).unwrap();
+        let notes = fs_tree.create_file("notes", docs).unwrap();
+        fs_tree.write(notes, "alpha\nbeta\nAlphabet\n").unwrap();
+        let sub = fs_tree.create_directory("sub", docs).unwrap();
+        let todo = fs_tree.create_file("todo", sub).unwrap();
+        fs_tree.write(todo, "gamma\nalpha\n").unwrap();
+        let image = fs_tree.create_file("image", sub).unwrap();
+        let bytes: &[u8] = b"\x89PNG\0alpha\xff";
+        fs_tree
+            .write(image, Contents::Binary(bytes.into()))
+            .unwrap();
+        Rc::new(RefCell::new(fs_tree))
+    }
+
+    #[test]
+    fn numbers_and_counts_matching_lines() {
+        let fs_tree = docs();
+        let mut cwd = PathBuf::from("/docs");
+        assert_eq!(
+            run("grep -n alpha notes", &mut cwd, &fs_tree),
+            (StatusCode(0), "1:alpha".into())
+        );
+        assert_eq!(
+            run("grep -in alpha notes", &mut cwd, &fs_tree).1,
+            "1:alpha\n3:Alphabet"
+        );
+        assert_eq!(run("grep -c a notes", &mut cwd, &fs_tree).1, "3");
+        assert_eq!(run("grep -vc beta notes", &mut cwd, &fs_tree).1, "2");
+        assert_eq!(
+            run("grep delta notes", &mut cwd, &fs_tree),
+            (StatusCode(1), String::new())
+        );
+    }
+
+    #[test]
+    fn searches_directories_recursively() {
+        let fs_tree = docs();
+        let mut cwd = PathBuf::from("/docs");
+        assert_eq!(
+            run("grep -r alpha", &mut cwd, &fs_tree).1,
+            "notes:alpha\nBinary file sub/image matches\nsub/todo:alpha"
+        );
+        assert_eq!(
+            run("grep -rn gamma /docs", &mut cwd, &fs_tree).1,
+            "/docs/sub/todo:1:gamma"
+        );
+        assert_eq!(
+            run("grep -rc alpha sub", &mut cwd, &fs_tree).1,
+            "sub/image:1\nsub/todo:1"
+        );
+
+        let (status, output) = run("grep alpha sub", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(2));
+        assert_eq!(output, "leash: grep: is a directory: sub");
+    }
+}
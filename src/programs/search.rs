@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+
+use unix_path::{Path, PathBuf};
+use yew::{Html, classes, html};
+
+use super::join_lines;
+use crate::{
+    History, StatusCode, display_path,
+    fs::{FsIndex, FsTree, tokens},
+    parse::quote,
+};
+
+/// How many results are shown at most.
+const MAX_RESULTS: usize = 10;
+/// How many characters of a line are shown around the first match.
+const SNIPPET_WIDTH: usize = 80;
+
+/// Finds a path to each of `wanted`, walking the tree in sorted order without following
+/// symbolic links.
+fn find_paths(fs_tree: &FsTree, wanted: &HashSet<FsIndex>) -> HashMap<FsIndex, PathBuf> {
+    let mut paths = HashMap::new();
+    let mut pending = vec![(fs_tree.root(), PathBuf::from("/"))];
+    while let Some((directory, path)) = pending.pop() {
+        let Ok(entries) = fs_tree.iter_children(directory) else {
+            continue;
+        };
+        let mut entries = entries.collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|entry| entry.name());
+        for entry in entries.into_iter().rev() {
+            let entry_path = path.join(&*entry.name());
+            if wanted.contains(&entry.index()) {
+                paths
+                    .entry(entry.index())
+                    .or_insert_with(|| entry_path.clone());
+            }
+            if fs_tree.is_directory(entry.index()).unwrap_or(false) {
+                pending.push((entry.index(), entry_path));
+            }
+        }
+    }
+    paths
+}
+
+/// Picks the first line of `text` containing one of `terms` and renders it with every
+/// occurrence highlighted, cut down to about [`SNIPPET_WIDTH`] characters.
+fn snippet(text: &str, terms: &HashSet<String>) -> Option<Html> {
+    let (line, first) = text.lines().find_map(|line| {
+        tokens(line)
+            .find(|(_, term)| terms.contains(term))
+            .map(|(range, _)| (line, range.start))
+    })?;
+
+    // long lines start a little before the first match, at the beginning of a word
+    let start = match line[..first].char_indices().rev().nth(SNIPPET_WIDTH / 4) {
+        Some(_) if line.chars().count() <= SNIPPET_WIDTH => 0,
+        Some((start, _)) => line[start..first]
+            .find(char::is_whitespace)
+            .map_or(first, |space| start + space + 1),
+        None => 0,
+    };
+    let end = line[start..]
+        .char_indices()
+        .nth(SNIPPET_WIDTH)
+        .map_or(line.len(), |(i, _)| start + i);
+    let excerpt = &line[start..end];
+
+    let mut parts = Vec::new();
+    if start > 0 {
+        parts.push(html! {<>{"…"}</>});
+    }
+    let mut shown = 0;
+    for (range, term) in tokens(excerpt) {
+        if terms.contains(&term) {
+            parts.push(html! {<>{&excerpt[shown..range.start]}</>});
+            parts.push(html! {
+                <span class={classes!("text-rose-400", "font-bold")}>{&excerpt[range.clone()]}</span>
+            });
+            shown = range.end;
+        }
+    }
+    parts.push(html! {<>{&excerpt[shown..]}</>});
+    if end < line.len() {
+        parts.push(html! {<>{"…"}</>});
+    }
+
+    Some(html! {<span class={classes!("whitespace-pre-wrap")}>{"  "}{for parts}</span>})
+}
+
+/// Searches the text of every file for the given words, listing the best matches with the line
+/// they occur on. Clicking a result shows the file.
+pub(super) fn search(
+    args: &[String],
+    _cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let query = args[1..].join(" ");
+    let terms = tokens(&query).map(|(_, term)| term).collect::<HashSet<_>>();
+    if terms.is_empty() {
+        let _ = history.write(html! {<>{"usage: search term ..."}</>});
+        return StatusCode(2);
+    }
+
+    let ranked = fs_tree.search(&query);
+    let paths = find_paths(fs_tree, &ranked.iter().map(|(index, _)| *index).collect());
+    // files that are indexed but unreachable have no path to show
+    let mut results = ranked
+        .into_iter()
+        .filter_map(|(index, score)| Some((paths.get(&index)?, index, score)))
+        .collect::<Vec<_>>();
+    results.sort_by(|(a_path, _, a), (b_path, _, b)| b.total_cmp(a).then(a_path.cmp(b_path)));
+    if results.is_empty() {
+        return StatusCode(1);
+    }
+
+    let _ = history.write(join_lines(results.into_iter().take(MAX_RESULTS).map(
+        |(path, index, _)| {
+            let command = format!("cat {}", quote(&path.to_string_lossy()));
+            let snippet = fs_tree
+                .read_text(index)
+                .ok()
+                .and_then(|text| snippet(&text, &terms));
+            html! {
+                <>
+                    <span data-command={command} class={classes!("underline", "cursor-pointer", "text-blue-400", "hover:text-blue-300")}>
+                        {display_path(Path::new(path))}
+                    </span>
+                    if let Some(snippet) = snippet {
+                        <br />
+                        {snippet}
+                    }
+                </>
+            }
+        },
+    )));
+    StatusCode(0)
+}
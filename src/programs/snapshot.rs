@@ -0,0 +1,177 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use unix_path::PathBuf;
+use yew::{classes, html};
+
+use super::{format_time, invalid_option, join_lines, parse_flags, write_lines};
+use crate::{
+    History, StatusCode,
+    fs::{FsTree, Snapshot},
+    validate_cwd,
+};
+
+const USAGE: &str = "usage: snapshot [-l] | snapshot name | snapshot -d name ...";
+
+thread_local! {
+    /// Snapshots taken during this visit, by name. They are not saved with the tree.
+    static SNAPSHOTS: RefCell<BTreeMap<String, Snapshot>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Lists every snapshot in name order, with when it was taken and how many nodes it holds.
+fn list(history: &mut History) -> StatusCode {
+    let lines = SNAPSHOTS.with_borrow(|snapshots| {
+        snapshots
+            .iter()
+            .map(|(name, snapshot)| {
+                format!(
+                    "{} {:>6} {name}",
+                    format_time(snapshot.taken()),
+                    snapshot.node_count()
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    if !lines.is_empty() {
+        let _ =
+            history.write(join_lines(lines.into_iter().map(
+                |line| html! {<span class={classes!("whitespace-pre")}>{line}</span>},
+            )));
+    }
+    StatusCode(0)
+}
+
+/// Takes (`snapshot name`), lists (`snapshot` or `-l`) or deletes (`-d`) named snapshots of the
+/// whole filesystem, to be put back with `restore`.
+pub(super) fn snapshot(
+    args: &[String],
+    _cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "dl") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "snapshot", flag),
+    };
+
+    match (flags.contains('d'), flags.contains('l'), &operands[..]) {
+        (false, _, []) => list(history),
+        (false, false, [name]) => {
+            let taken = SNAPSHOTS.with_borrow_mut(|snapshots| {
+                if snapshots.contains_key(*name) {
+                    return false;
+                }
+                snapshots.insert((*name).into(), fs_tree.snapshot());
+                true
+            });
+            if taken {
+                StatusCode(0)
+            } else {
+                write_lines(
+                    history,
+                    &[format!("leash: snapshot: snapshot already exists: {name}")],
+                );
+                StatusCode(1)
+            }
+        }
+        (true, false, names) if !names.is_empty() => {
+            let missing = SNAPSHOTS.with_borrow_mut(|snapshots| {
+                names
+                    .iter()
+                    .filter(|name| snapshots.remove(**name).is_none())
+                    .map(|name| format!("leash: snapshot: no such snapshot: {name}"))
+                    .collect::<Vec<_>>()
+            });
+            write_lines(history, &missing);
+            if missing.is_empty() {
+                StatusCode(0)
+            } else {
+                StatusCode(1)
+            }
+        }
+        _ => {
+            let _ = history.write(html! {<>{USAGE}</>});
+            StatusCode(2)
+        }
+    }
+}
+
+/// Puts the filesystem back as it was when the named snapshot was taken. The snapshot is kept,
+/// so it can be restored again.
+pub(super) fn restore(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let [_, name] = args else {
+        let _ = history.write(html! {<>{"usage: restore name"}</>});
+        return StatusCode(2);
+    };
+
+    let Some(snapshot) = SNAPSHOTS.with_borrow(|snapshots| snapshots.get(name).cloned()) else {
+        write_lines(
+            history,
+            &[format!("leash: restore: no such snapshot: {name}")],
+        );
+        return StatusCode(1);
+    };
+    fs_tree.restore(&snapshot);
+    // the working directory may not exist in the restored tree
+    validate_cwd(cwd, fs_tree);
+    StatusCode(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::{Path, PathBuf};
+
+    use crate::{StatusCode, fs::FsTree, programs::tests::run};
+
+    #[test]
+    fn restore_puts_back_deleted_content_and_resets_cwd() {
+        let mut fs_tree = FsTree::new();
+        let notes = fs_tree.create_file("notes", fs_tree.root()).unwrap();
+        fs_tree.write(notes, "first draft\n").unwrap();
+        let fs_tree = Rc::new(RefCell::new(fs_tree));
+        let mut cwd = PathBuf::from("/");
+
+        assert_eq!(run("snapshot before", &mut cwd, &fs_tree).0, StatusCode(0));
+        let (status, output) = run("snapshot before", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(1));
+        assert_eq!(output, "leash: snapshot: snapshot already exists: before");
+
+        run("echo rewritten > notes", &mut cwd, &fs_tree);
+        assert_eq!(run("rm notes", &mut cwd, &fs_tree).0, StatusCode(0));
+        let root = fs_tree.borrow().root();
+        fs_tree
+            .borrow_mut()
+            .create_directory("later", root)
+            .unwrap();
+        run("cd later", &mut cwd, &fs_tree);
+        assert_eq!(cwd, Path::new("/later"));
+
+        assert_eq!(
+            run("restore before", &mut cwd, &fs_tree),
+            (StatusCode(0), String::new())
+        );
+        assert_eq!(cwd, Path::new("/"));
+        assert_eq!(run("cat notes", &mut cwd, &fs_tree).1, "first draft");
+        assert!(fs_tree.borrow().lookup_path(Path::new("/later")).is_err());
+
+        // the snapshot is kept until deleted
+        assert_eq!(run("restore before", &mut cwd, &fs_tree).0, StatusCode(0));
+        assert_eq!(
+            run("snapshot -d before", &mut cwd, &fs_tree).0,
+            StatusCode(0)
+        );
+        assert_eq!(
+            run("restore before", &mut cwd, &fs_tree),
+            (
+                StatusCode(1),
+                "leash: restore: no such snapshot: before".into()
+            )
+        );
+    }
+}
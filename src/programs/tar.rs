@@ -0,0 +1,151 @@
+use unix_path::{Path, PathBuf};
+use yew::html;
+
+use super::{error_message, invalid_option, parse_flags, write_lines};
+use crate::{
+    History, StatusCode,
+    fs::{Contents, FsTree},
+    open_or_create, resolve_path, tar,
+    transfer::{self, Action},
+};
+
+const USAGE: &str = "usage: tar -c [-v] [-f archive] path ... | tar -x [-v] [-f archive]";
+
+/// Archives `paths` into `archive`, or offers the archive as a download if there is none.
+fn create(
+    paths: &[&str],
+    archive: Option<&str>,
+    verbose: bool,
+    cwd: &Path,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    if paths.is_empty() {
+        write_lines(
+            history,
+            &["leash: tar: cowardly refusing to create an empty archive".into()],
+        );
+        return StatusCode(2);
+    }
+
+    let mut lines = Vec::new();
+    let mut status = StatusCode(0);
+    let mut entries = Vec::new();
+    for path in paths {
+        match tar::archive(fs_tree, cwd, path) {
+            Ok((archived, skipped)) => {
+                lines.extend(
+                    skipped
+                        .into_iter()
+                        .map(|path| format!("leash: tar: {path}: character device ignored")),
+                );
+                entries.extend(archived);
+            }
+            Err(error) => {
+                lines.push(error_message("tar", error, path));
+                status = error.status();
+            }
+        }
+    }
+    if verbose {
+        lines.extend(entries.iter().map(|entry| entry.path.clone()));
+    }
+
+    let result = tar::write(&entries).map_err(|error| error.to_string());
+    let result = result.and_then(|bytes| match archive {
+        Some(archive) => open_or_create(archive, cwd, fs_tree)
+            .and_then(|index| fs_tree.write(index, Contents::Binary(bytes.into())))
+            .map_err(|error| format!("{error}: {archive}")),
+        None => {
+            // name the download after what it holds, if it holds one thing
+            let name = match paths {
+                [path] => Path::new(path)
+                    .file_name()
+                    .map(|name| format!("{}.tar", name.to_string_lossy())),
+                _ => None,
+            };
+            transfer::download(
+                name.as_deref().unwrap_or("archive.tar"),
+                &bytes,
+                "application/x-tar",
+            )
+            .map_err(|error| error.to_string())
+        }
+    });
+    if let Err(error) = result {
+        lines.push(format!("leash: tar: {error}"));
+        status = StatusCode(2);
+    }
+
+    write_lines(history, &lines);
+    status
+}
+
+/// Extracts `archive` into the working directory, or asks the visitor for an archive to upload
+/// if there is none.
+fn extract(
+    archive: Option<&str>,
+    verbose: bool,
+    cwd: &Path,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let Some(archive) = archive else {
+        let _ = history.write(html! {<>{"tar: choose an archive to extract"}</>});
+        transfer::request_upload(
+            Action::Extract {
+                cwd: cwd.to_path_buf(),
+                verbose,
+            },
+            history,
+        );
+        return StatusCode(0);
+    };
+
+    let contents =
+        match resolve_path(archive, cwd, fs_tree).and_then(|(index, _)| fs_tree.contents(index)) {
+            Ok(contents) => contents,
+            Err(error) => {
+                write_lines(history, &[error_message("tar", error, archive)]);
+                return error.status();
+            }
+        };
+
+    let (lines, status) = tar::unpack(fs_tree, cwd, contents.bytes(), verbose);
+    write_lines(history, &lines);
+    status
+}
+
+/// Creates (`-c`) or extracts (`-x`) ustar archives. `-f` names the archive in the filesystem;
+/// without it, created archives are downloaded and extracted ones uploaded. `-v` lists members.
+pub(super) fn tar(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (flags, operands) = match parse_flags(args, "cxvf") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "tar", flag),
+    };
+    let verbose = flags.contains('v');
+
+    // as in real tar, `f` takes the first operand as the archive
+    let (archive, paths) = match (flags.contains('f'), operands.split_first()) {
+        (true, Some((archive, paths))) => (Some(*archive), paths),
+        (false, _) => (None, &operands[..]),
+        (true, None) => {
+            let _ = history.write(html! {<>{USAGE}</>});
+            return StatusCode(2);
+        }
+    };
+
+    match (flags.contains('c'), flags.contains('x')) {
+        (true, false) => create(paths, archive, verbose, cwd, fs_tree, history),
+        (false, true) if paths.is_empty() => extract(archive, verbose, cwd, fs_tree, history),
+        _ => {
+            let _ = history.write(html! {<>{USAGE}</>});
+            StatusCode(2)
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use unix_path::PathBuf;
+use yew::{classes, html};
+
+use super::{display_name, error_message, invalid_option, join_lines, parse_flags, take_value};
+use crate::{
+    History, StatusCode,
+    fs::{FsIndex, FsTree},
+    resolve_path,
+};
+
+struct Options {
+    /// `-a`: include names starting with a dot.
+    all: bool,
+    /// `-d`: list directories only.
+    directories_only: bool,
+    /// `-L`: how many levels below each path to descend.
+    max_depth: usize,
+}
+
+#[derive(Default)]
+struct Counts {
+    directories: usize,
+    files: usize,
+}
+
+impl Counts {
+    /// The closing line, such as `2 directories, 1 file`.
+    fn summary(&self, directories_only: bool) -> String {
+        let plural = |count: usize, one: &str, many: &str| match count {
+            1 => format!("1 {one}"),
+            count => format!("{count} {many}"),
+        };
+        let directories = plural(self.directories, "directory", "directories");
+        match directories_only {
+            true => directories,
+            false => format!("{directories}, {}", plural(self.files, "file", "files")),
+        }
+    }
+}
+
+/// Draws the entries below `index` into `lines`, one per line in name order, with box-drawing
+/// branches after `indent`. Symbolic links are shown but not followed.
+fn draw(
+    fs_tree: &FsTree,
+    index: FsIndex,
+    indent: &str,
+    depth: usize,
+    options: &Options,
+    counts: &mut Counts,
+    lines: &mut Vec<String>,
+) {
+    if depth >= options.max_depth {
+        return;
+    }
+    let Ok(entries) = fs_tree.iter_children(index) else {
+        return;
+    };
+    let mut entries = entries
+        .filter(|entry| options.all || !entry.name().starts_with('.'))
+        .map(|entry| {
+            let is_directory = fs_tree.is_directory(entry.index()).unwrap_or(false);
+            (entry, is_directory)
+        })
+        .filter(|(_, is_directory)| *is_directory || !options.directories_only)
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(entry, _)| entry.name());
+
+    let last = entries.len().saturating_sub(1);
+    for (i, (entry, is_directory)) in entries.into_iter().enumerate() {
+        let (branch, continuation) = match i == last {
+            true => ("└── ", "    "),
+            false => ("├── ", "│   "),
+        };
+        lines.push(format!(
+            "{indent}{branch}{}",
+            display_name(fs_tree, &entry.name(), entry.index())
+        ));
+        if is_directory {
+            counts.directories += 1;
+            let indent = format!("{indent}{continuation}");
+            draw(
+                fs_tree,
+                entry.index(),
+                &indent,
+                depth + 1,
+                options,
+                counts,
+                lines,
+            );
+        } else {
+            counts.files += 1;
+        }
+    }
+}
+
+/// Lists the contents of directories as an indented tree, followed by a count of what was
+/// shown. `-L` limits the depth, `-a` includes hidden names and `-d` lists directories only.
+pub(super) fn tree(
+    args: &[String],
+    cwd: &mut PathBuf,
+    fs_tree: &mut FsTree,
+    history: &mut History,
+) -> StatusCode {
+    let (level, args) = match take_value(args, 'L') {
+        Ok(taken) => taken,
+        Err(flag) => {
+            let _ = history.write(html! {
+                <>{format!("leash: tree: option requires an argument -- '{flag}'")}</>
+            });
+            return StatusCode(2);
+        }
+    };
+    let (flags, mut paths) = match parse_flags(&args, "ad") {
+        Ok(parsed) => parsed,
+        Err(flag) => return invalid_option(history, "tree", flag),
+    };
+    let max_depth = match level.as_deref().map(str::parse::<usize>) {
+        None => usize::MAX,
+        Some(Ok(level)) if level > 0 => level,
+        Some(_) => {
+            let _ = history.write(html! {
+                <>{format!("leash: tree: invalid level: {}", level.unwrap_or_default())}</>
+            });
+            return StatusCode(2);
+        }
+    };
+    let options = Options {
+        all: flags.contains('a'),
+        directories_only: flags.contains('d'),
+        max_depth,
+    };
+    if paths.is_empty() {
+        paths.push(".");
+    }
+
+    let mut status = StatusCode(0);
+    let mut counts = Counts::default();
+    let mut lines = Vec::new();
+    for path in paths {
+        match resolve_path(path, cwd, fs_tree) {
+            Ok((index, _)) => {
+                lines.push(path.to_owned());
+                if fs_tree.is_directory(index).unwrap_or(false) {
+                    draw(fs_tree, index, "", 0, &options, &mut counts, &mut lines);
+                } else {
+                    counts.files += 1;
+                }
+            }
+            Err(error) => {
+                lines.push(error_message("tree", error, path));
+                status = error.status();
+            }
+        }
+    }
+    lines.push(String::new());
+    lines.push(counts.summary(options.directories_only));
+
+    let _ =
+        history.write(join_lines(lines.into_iter().map(
+            |line| html! {<span class={classes!("whitespace-pre")}>{line}</span>},
+        )));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use unix_path::PathBuf;
+
+    use crate::{StatusCode, fs::FsTree, programs::tests::run};
+
+    fn nested() -> Rc<RefCell<FsTree>> {
+        let mut fs_tree = FsTree::new();
+        let p = fs_tree.create_directory("p", fs_tree.root()).unwrap();
+        fs_tree.create_file("a", p).unwrap();
+        fs_tree.create_file(".hidden", p).unwrap();
+        let sub = fs_tree.create_directory("sub", p).unwrap();
+        fs_tree.create_file("b", sub).unwrap();
+        let deep = fs_tree.create_directory("deep", sub).unwrap();
+        fs_tree.create_file("c", deep).unwrap();
+        Rc::new(RefCell::new(fs_tree))
+    }
+
+    #[test]
+    fn draws_and_counts_everything_shown() {
+        let fs_tree = nested();
+        let mut cwd = PathBuf::from("/");
+        let (status, output) = run("tree p", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(0));
+        assert_eq!(
+            output.lines().collect::<Vec<_>>(),
+            [
+                "p",
+                "├── a",
+                "└── sub",
+                "    ├── b",
+                "    └── deep",
+                "        └── c",
+                "",
+                "2 directories, 3 files",
+            ]
+        );
+        assert!(
+            run("tree -a p", &mut cwd, &fs_tree)
+                .1
+                .ends_with("2 directories, 4 files")
+        );
+        assert!(
+            run("tree -d p", &mut cwd, &fs_tree)
+                .1
+                .ends_with("\n\n2 directories")
+        );
+    }
+
+    #[test]
+    fn counts_only_the_levels_shown() {
+        let fs_tree = nested();
+        let mut cwd = PathBuf::from("/p");
+        assert_eq!(
+            run("tree -L 1", &mut cwd, &fs_tree).1,
+            ".\n├── a\n└── sub\n\n1 directory, 1 file"
+        );
+        assert!(
+            run("tree -L 2", &mut cwd, &fs_tree)
+                .1
+                .ends_with("2 directories, 2 files")
+        );
+
+        let (status, output) = run("tree -L 0", &mut cwd, &fs_tree);
+        assert_eq!(status, StatusCode(2));
+        assert_eq!(output, "leash: tree: invalid level: 0");
+    }
+}
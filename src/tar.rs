@@ -0,0 +1,490 @@
+//! Reading and writing ustar archives, and moving whole subtrees of the filesystem in and out of
+//! them.
+
+use std::{collections::HashMap, str};
+
+use thiserror::Error;
+use unix_path::{Component, Path};
+
+use crate::{
+    StatusCode,
+    fs::{Contents, FileType, FsError, FsIndex, FsTree},
+    resolve_path, resolve_path_nofollow,
+};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Error)]
+pub enum TarError {
+    #[error("not a tar archive")]
+    InvalidHeader,
+    #[error("unexpected end of archive")]
+    Truncated,
+    #[error("file name too long for ustar: {0}")]
+    NameTooLong(String),
+    #[error("size or time out of range for ustar: {0}")]
+    OutOfRange(String),
+    #[error(transparent)]
+    Filesystem(#[from] FsError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File(Vec<u8>),
+    Directory,
+    Symlink(String),
+}
+
+/// A member of an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits, if the archive records them.
+    pub mode: Option<u16>,
+    /// Modification time in seconds since the epoch.
+    pub mtime: u64,
+}
+
+/// Writes `value` as a NUL-terminated octal number filling `field`, returning `None` if it has
+/// too many digits to fit.
+fn write_octal(field: &mut [u8], value: u64) -> Option<()> {
+    let digits = format!("{value:0width$o}", width = field.len().saturating_sub(1));
+    if digits.len() >= field.len() {
+        return None;
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    Some(())
+}
+
+fn read_octal(field: &[u8]) -> Result<u64, TarError> {
+    let digits = str::from_utf8(field).map_err(|_| TarError::InvalidHeader)?;
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| TarError::InvalidHeader)
+}
+
+/// Reads a NUL-padded string field.
+fn read_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Splits a path that doesn't fit the 100-byte name field across the prefix field as well.
+fn split_name(path: &str) -> Result<(&str, &str), TarError> {
+    if path.len() <= 100 {
+        return Ok(("", path));
+    }
+    path.char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100 && i > 0)
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .next()
+        .ok_or_else(|| TarError::NameTooLong(path.into()))
+}
+
+fn header(entry: &Entry) -> Result<[u8; BLOCK_SIZE], TarError> {
+    let mut header = [0; BLOCK_SIZE];
+    let (prefix, name) = split_name(&entry.path)?;
+    let (typeflag, size, link) = match &entry.kind {
+        EntryKind::File(bytes) => (b'0', bytes.len(), ""),
+        EntryKind::Directory => (b'5', 0, ""),
+        EntryKind::Symlink(target) if target.len() <= 100 => (b'2', 0, target.as_str()),
+        EntryKind::Symlink(target) => return Err(TarError::NameTooLong(target.clone())),
+    };
+
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    let out_of_range = || TarError::OutOfRange(entry.path.clone());
+    write_octal(
+        &mut header[100..108],
+        u64::from(entry.mode.unwrap_or(0o644) & 0o7777),
+    )
+    .ok_or_else(out_of_range)?;
+    write_octal(&mut header[108..116], 0).ok_or_else(out_of_range)?;
+    write_octal(&mut header[116..124], 0).ok_or_else(out_of_range)?;
+    write_octal(&mut header[124..136], size as u64).ok_or_else(out_of_range)?;
+    write_octal(&mut header[136..148], entry.mtime).ok_or_else(out_of_range)?;
+    header[156] = typeflag;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[265..269].copy_from_slice(b"user");
+    header[297..301].copy_from_slice(b"user");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // the checksum is taken with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|&b| u64::from(b)).sum::<u64>();
+    write_octal(&mut header[148..155], checksum).ok_or_else(out_of_range)?;
+    Ok(header)
+}
+
+/// Serializes `entries` as a ustar archive.
+pub fn write(entries: &[Entry]) -> Result<Vec<u8>, TarError> {
+    let mut archive = Vec::new();
+    for entry in entries {
+        archive.extend_from_slice(&header(entry)?);
+        if let EntryKind::File(bytes) = &entry.kind {
+            archive.extend_from_slice(bytes);
+            archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+    }
+    // an archive ends with two empty blocks
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    Ok(archive)
+}
+
+/// Parses a ustar archive. GNU long names and the path fields of pax headers are understood,
+/// as real tar produces them for long paths; members of other types are skipped.
+pub fn read(archive: &[u8]) -> Result<Vec<Entry>, TarError> {
+    let mut entries = Vec::new();
+    let mut long_name = None;
+    let mut long_link = None;
+    let mut offset = 0;
+
+    loop {
+        let Some(header) = archive.get(offset..offset + BLOCK_SIZE) else {
+            // tolerate a missing end marker, but not a partial block
+            return match offset {
+                0 => Err(TarError::InvalidHeader),
+                offset if offset < archive.len() => Err(TarError::Truncated),
+                _ => Ok(entries),
+            };
+        };
+        if header.iter().all(|&b| b == 0) {
+            return Ok(entries);
+        }
+        if !header[257..262].eq(b"ustar") {
+            return Err(TarError::InvalidHeader);
+        }
+        let expected = read_octal(&header[148..156])?;
+        let checksum = header[..148]
+            .iter()
+            .chain(&[b' '; 8])
+            .chain(&header[156..])
+            .map(|&b| u64::from(b))
+            .sum::<u64>();
+        if checksum != expected {
+            return Err(TarError::InvalidHeader);
+        }
+
+        // a size beyond the end of the archive, however large, only means the data is missing
+        let size =
+            usize::try_from(read_octal(&header[124..136])?).map_err(|_| TarError::Truncated)?;
+        let data_start = offset + BLOCK_SIZE;
+        let data_end = data_start.checked_add(size).ok_or(TarError::Truncated)?;
+        let data = archive
+            .get(data_start..data_end)
+            .ok_or(TarError::Truncated)?;
+        offset = data_start + size.next_multiple_of(BLOCK_SIZE);
+
+        // these describe the member that follows them
+        match header[156] {
+            b'L' => {
+                long_name = Some(read_string(data));
+                continue;
+            }
+            b'K' => {
+                long_link = Some(read_string(data));
+                continue;
+            }
+            b'x' => {
+                let records = pax_records(data);
+                long_name = records.get("path").cloned();
+                long_link = records.get("linkpath").cloned();
+                continue;
+            }
+            _ => {}
+        }
+
+        let prefix = read_string(&header[345..500]);
+        let name = read_string(&header[..100]);
+        let path = match prefix.as_str() {
+            "" => name,
+            prefix => format!("{prefix}/{name}"),
+        };
+        let path = long_name.take().unwrap_or(path);
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| read_string(&header[157..257]));
+        let mode = match header[100..108].iter().all(|&b| b == 0 || b == b' ') {
+            true => None,
+            false => Some((read_octal(&header[100..108])? & 0o7777) as u16),
+        };
+        let mtime = read_octal(&header[136..148])?;
+
+        let kind = match header[156] {
+            b'0' | b'\0' | b'7' => EntryKind::File(data.to_vec()),
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink(link),
+            _ => continue,
+        };
+
+        entries.push(Entry {
+            path,
+            kind,
+            mode,
+            mtime,
+        });
+    }
+}
+
+/// Parses the `length key=value\n` records of a pax extended header.
+fn pax_records(data: &[u8]) -> HashMap<String, String> {
+    let mut records = HashMap::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let Some(length) = str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|&length| length > space && length <= rest.len())
+        else {
+            break;
+        };
+        let record = String::from_utf8_lossy(&rest[space + 1..length - 1]);
+        if let Some((key, value)) = record.split_once('=') {
+            records.insert(key.into(), value.into());
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+
+/// Collects the entry for `index` and, if it is a directory, everything beneath it in sorted
+/// order. Symbolic links are archived as links; devices can't be, and are reported in `skipped`.
+fn collect(
+    fs_tree: &FsTree,
+    path: &str,
+    index: FsIndex,
+    entries: &mut Vec<Entry>,
+    skipped: &mut Vec<String>,
+) -> Result<(), FsError> {
+    let metadata = fs_tree.metadata(index)?;
+    let kind = match metadata.file_type() {
+        FileType::File => EntryKind::File(fs_tree.contents(index)?.bytes().to_vec()),
+        FileType::Directory => EntryKind::Directory,
+        FileType::Symlink => EntryKind::Symlink(fs_tree.read_link(index)?.to_string()),
+        FileType::CharDevice => {
+            skipped.push(path.into());
+            return Ok(());
+        }
+    };
+    let is_directory = kind == EntryKind::Directory;
+    entries.push(Entry {
+        path: match is_directory && !path.ends_with('/') {
+            true => format!("{path}/"),
+            false => path.into(),
+        },
+        kind,
+        mode: Some(metadata.mode()),
+        mtime: metadata.modified() / 1000,
+    });
+
+    if is_directory {
+        let mut children = fs_tree.iter_children(index)?.collect::<Vec<_>>();
+        children.sort_unstable_by_key(|entry| entry.name());
+        for child in children {
+            let path = format!("{}/{}", path.trim_end_matches('/'), child.name());
+            collect(fs_tree, &path, child.index(), entries, skipped)?;
+        }
+    }
+    Ok(())
+}
+
+/// Archives `path`, relative to `cwd`, with everything beneath it. Members are named after the
+/// path as given, less any leading `/`. Returns the entries and the paths of devices left out.
+pub fn archive(
+    fs_tree: &FsTree,
+    cwd: &Path,
+    path: &str,
+) -> Result<(Vec<Entry>, Vec<String>), FsError> {
+    let (index, _) = resolve_path_nofollow(path, cwd, fs_tree)?;
+    let name = match path.trim_start_matches('/') {
+        "" => ".",
+        name => name,
+    };
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    collect(fs_tree, name, index, &mut entries, &mut skipped)?;
+    Ok((entries, skipped))
+}
+
+fn child(fs_tree: &FsTree, directory: FsIndex, name: &str) -> Result<Option<FsIndex>, FsError> {
+    Ok(fs_tree
+        .iter_dir(directory)?
+        .find(|child| &*child.name() == name)
+        .map(|child| child.index()))
+}
+
+/// Creates `entry` beneath `cwd`, along with any directories leading to it. Existing files and
+/// links are replaced; existing directories are kept. Paths that are absolute or climb out with
+/// `..` are refused, as real tar does by default.
+pub fn extract(fs_tree: &mut FsTree, cwd: &Path, entry: &Entry) -> Result<(), FsError> {
+    let path = Path::new(&entry.path);
+    if path
+        .components()
+        .any(|component| matches!(component, Component::RootDir | Component::ParentDir))
+    {
+        return Err(FsError::InvalidName);
+    }
+    let components = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let Some((name, parents)) = components.split_last() else {
+        // `./` itself stands for the directory being extracted into
+        return Ok(());
+    };
+
+    let (mut parent, _) = resolve_path(".", cwd, fs_tree)?;
+    for directory in parents {
+        parent = match child(fs_tree, parent, directory)? {
+            Some(index) if fs_tree.is_directory(index)? => index,
+            Some(_) => return Err(FsError::NotADirectory),
+            None => fs_tree.create_directory(directory, parent)?,
+        };
+    }
+
+    let existing = child(fs_tree, parent, name)?;
+    let index = match (&entry.kind, existing) {
+        (EntryKind::Directory, Some(index)) if fs_tree.is_directory(index)? => index,
+        (_, Some(index)) if fs_tree.is_directory(index)? => return Err(FsError::IsADirectory),
+        (EntryKind::File(_), Some(index)) if fs_tree.is_file(index)? => index,
+        (kind, existing) => {
+            if existing.is_some() {
+                fs_tree.delete_recursive(name, parent)?;
+            }
+            match kind {
+                EntryKind::File(_) => fs_tree.create_file(name, parent)?,
+                EntryKind::Directory => fs_tree.create_directory(name, parent)?,
+                EntryKind::Symlink(target) => fs_tree.create_symlink(name, parent, target)?,
+            }
+        }
+    };
+
+    if let EntryKind::File(bytes) = &entry.kind {
+        fs_tree.write(index, Contents::from_bytes(bytes))?;
+    }
+    if let Some(mode) = entry.mode
+        && !matches!(entry.kind, EntryKind::Symlink(_))
+    {
+        fs_tree.set_mode(index, mode)?;
+    }
+    Ok(())
+}
+
+/// Extracts every member of `archive` into `cwd`, returning what to report, which includes the
+/// member names if `verbose`, and the status to exit with.
+pub fn unpack(
+    fs_tree: &mut FsTree,
+    cwd: &Path,
+    archive: &[u8],
+    verbose: bool,
+) -> (Vec<String>, StatusCode) {
+    let entries = match read(archive) {
+        Ok(entries) => entries,
+        Err(error) => return (vec![format!("leash: tar: {error}")], StatusCode(2)),
+    };
+
+    let mut lines = Vec::new();
+    let mut status = StatusCode(0);
+    for entry in &entries {
+        match extract(fs_tree, cwd, entry) {
+            Ok(()) if verbose => lines.push(entry.path.clone()),
+            Ok(()) => {}
+            Err(error) => {
+                lines.push(format!("leash: tar: {error}: {}", entry.path));
+                status = StatusCode(2);
+            }
+        }
+    }
+    (lines, status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BLOCK_SIZE, Entry, EntryKind, TarError, read, write};
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                path: "notes/".into(),
+                kind: EntryKind::Directory,
+                mode: Some(0o755),
+                mtime: 1_700_000_000,
+            },
+            Entry {
+                path: format!("notes/{}/todo.txt", "nested".repeat(20)),
+                kind: EntryKind::File(b"buy milk\n".repeat(100)),
+                mode: Some(0o600),
+                mtime: 1_700_000_001,
+            },
+            Entry {
+                path: "notes/latest".into(),
+                kind: EntryKind::Symlink("todo.txt".into()),
+                mode: Some(0o777),
+                mtime: 0,
+            },
+        ]
+    }
+
+    /// Rewrites the checksum of the header at `offset` after it has been tampered with.
+    fn fix_checksum(archive: &mut [u8], offset: usize) {
+        let header = &mut archive[offset..offset + BLOCK_SIZE];
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|&b| u32::from(b)).sum::<u32>();
+        header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+    }
+
+    #[test]
+    fn round_trips_entries() {
+        let archive = write(&entries()).unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        assert_eq!(read(&archive).unwrap(), entries());
+    }
+
+    #[test]
+    fn refuses_values_too_large_for_a_header() {
+        let entry = Entry {
+            mtime: u64::MAX,
+            ..entries().remove(0)
+        };
+        assert!(matches!(
+            write(&[entry]),
+            Err(TarError::OutOfRange(path)) if path == "notes/"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut archive = write(&entries()).unwrap();
+        archive[0] = b'm';
+        assert!(matches!(read(&archive), Err(TarError::InvalidHeader)));
+    }
+
+    #[test]
+    fn rejects_a_truncated_block() {
+        let archive = write(&entries()).unwrap();
+        assert!(matches!(
+            read(&archive[..BLOCK_SIZE + 100]),
+            Err(TarError::Truncated)
+        ));
+        assert!(matches!(
+            read(&archive[..BLOCK_SIZE / 2]),
+            Err(TarError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn rejects_an_oversized_size() {
+        let mut archive = write(&entries()[..1]).unwrap();
+        archive[124..136].copy_from_slice(b"77777777777\0");
+        fix_checksum(&mut archive, 0);
+        assert!(matches!(read(&archive), Err(TarError::Truncated)));
+    }
+}
@@ -0,0 +1,289 @@
+//! Moving files between the visitor's machine and the filesystem: browser downloads, and uploads
+//! through a file picker or by dropping files onto the page.
+
+use std::cell::RefCell;
+
+use thiserror::Error;
+use unix_path::{Path, PathBuf};
+use yew::{Html, html};
+
+use crate::{
+    ExecutionRecord, History, RecordId,
+    fs::{Contents, FsError, FsIndex, FsTree},
+    programs::join_lines,
+    resolve_path, tar,
+};
+
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("downloads need a browser")]
+    Unsupported,
+    #[error("the browser refused the download")]
+    Browser,
+}
+
+/// A file read from the visitor's machine.
+pub struct Upload {
+    pub name: String,
+    /// `None` if the browser couldn't read the file.
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// What to do with files once the visitor has picked them.
+pub enum Action {
+    /// Extract each file as a tar archive into `cwd`.
+    Extract { cwd: PathBuf, verbose: bool },
+    /// Save each file in `cwd`.
+    Upload { cwd: PathBuf },
+}
+
+/// An upload asked for by a program, along with the history record its results belong to.
+pub struct Request {
+    pub action: Action,
+    pub record: RecordId,
+}
+
+thread_local! {
+    static PENDING: RefCell<Option<Request>> = const { RefCell::new(None) };
+}
+
+/// Asks for a file picker once the current command has finished. Browsers only open pickers
+/// while handling the visitor's input, and the files arrive later, so programs leave the
+/// request for the shell to act on.
+pub fn request_upload(action: Action, history: &History) {
+    // programs always run with a record of their own to report to
+    let Some(record) = history.0.last().map(ExecutionRecord::id) else {
+        return;
+    };
+    PENDING.with_borrow_mut(|pending| *pending = Some(Request { action, record }));
+}
+
+/// Takes the upload requested by the last command, if any.
+pub fn take_request() -> Option<Request> {
+    PENDING.take()
+}
+
+impl Action {
+    /// The program that asked for the files, for error messages.
+    fn program(&self) -> &'static str {
+        match self {
+            Action::Extract { .. } => "tar",
+            Action::Upload { .. } => "upload",
+        }
+    }
+
+    /// Whether the visitor may pick more than one file.
+    pub fn accepts_multiple(&self) -> bool {
+        match self {
+            Action::Extract { .. } => false,
+            Action::Upload { .. } => true,
+        }
+    }
+
+    /// Carries out the action on `uploads`, returning its output.
+    pub fn complete(&self, uploads: Vec<Upload>, fs_tree: &mut FsTree) -> Html {
+        let mut lines = Vec::new();
+        for upload in uploads {
+            match (self, upload.bytes) {
+                (_, None) => lines.push(format!(
+                    "leash: {}: could not read file: {}",
+                    self.program(),
+                    upload.name
+                )),
+                (Action::Extract { cwd, verbose }, Some(bytes)) => {
+                    lines.extend(tar::unpack(fs_tree, cwd, &bytes, *verbose).0)
+                }
+                (Action::Upload { cwd }, Some(bytes)) => {
+                    lines.push(match save(fs_tree, cwd, &upload.name, &bytes) {
+                        Ok(name) if name == upload.name => format!("uploaded {name}"),
+                        Ok(name) => format!("uploaded {} as {name}", upload.name),
+                        Err(error) => format!("leash: upload: {error}: {}", upload.name),
+                    })
+                }
+            }
+        }
+        join_lines(lines.into_iter().map(|line| html! {<>{line}</>}))
+    }
+}
+
+/// The progress line shown while `count` files are being read.
+pub fn uploading(count: u32) -> String {
+    match count {
+        1 => "uploading 1 file…".into(),
+        count => format!("uploading {count} files…"),
+    }
+}
+
+/// Picks a name for `name` that is free in `dir`, numbering it before the extension if taken:
+/// `notes.txt` becomes `notes-1.txt`, then `notes-2.txt`.
+fn free_name(fs_tree: &FsTree, dir: FsIndex, name: &str) -> Result<String, FsError> {
+    let taken = |name: &str| {
+        fs_tree
+            .iter_dir(dir)
+            .map(|mut entries| entries.any(|entry| &*entry.name() == name))
+    };
+    if !taken(name)? {
+        return Ok(name.into());
+    }
+
+    // a leading dot starts a hidden name rather than an extension
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut number = 1;
+    loop {
+        let candidate = format!("{stem}-{number}{extension}");
+        if !taken(&candidate)? {
+            return Ok(candidate);
+        }
+        number += 1;
+    }
+}
+
+/// Saves an uploaded file in `cwd` under a free name, returning the name used.
+fn save(fs_tree: &mut FsTree, cwd: &Path, name: &str, bytes: &[u8]) -> Result<String, FsError> {
+    let (dir, _) = resolve_path(".", cwd, fs_tree)?;
+    let name = free_name(fs_tree, dir, name)?;
+    let index = fs_tree.create_file(&name, dir)?;
+    fs_tree.write(index, Contents::from_bytes(bytes))?;
+    Ok(name)
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::{cell::RefCell, rc::Rc};
+
+    use gloo_timers::callback::Timeout;
+    use wasm_bindgen::{JsCast, closure::Closure};
+    use web_sys::{
+        Blob, BlobPropertyBag, FileList, FileReader, HtmlAnchorElement, HtmlInputElement, Url,
+        js_sys::{Array, ArrayBuffer, Uint8Array},
+    };
+
+    use super::{TransferError, Upload};
+
+    /// Offers `bytes` to the visitor as a file called `name`.
+    pub fn download(name: &str, bytes: &[u8], mime: &str) -> Result<(), TransferError> {
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or(TransferError::Browser)?;
+
+        let options = BlobPropertyBag::new();
+        options.set_type(mime);
+        let parts = Array::of1(&Uint8Array::from(bytes));
+        let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+            .map_err(|_| TransferError::Browser)?;
+        let url = Url::create_object_url_with_blob(&blob).map_err(|_| TransferError::Browser)?;
+
+        let anchor = document
+            .create_element("a")
+            .ok()
+            .and_then(|element| element.dyn_into::<HtmlAnchorElement>().ok())
+            .ok_or(TransferError::Browser)?;
+        anchor.set_href(&url);
+        anchor.set_download(name);
+        anchor.click();
+
+        // give the browser a moment to start the download before letting go of the data
+        Timeout::new(1000, move || {
+            let _ = Url::revoke_object_url(&url);
+        })
+        .forget();
+        Ok(())
+    }
+
+    /// Reads every file in `files`, calling `on_load` with them, in order, once all are read.
+    /// A file the browser can't read still arrives, without its bytes.
+    pub fn read_files(files: &FileList, on_load: impl FnOnce(Vec<Upload>) + 'static) {
+        let count = files.length() as usize;
+        if count == 0 {
+            return on_load(Vec::new());
+        }
+
+        let loaded = Rc::new(RefCell::new(Vec::with_capacity(count)));
+        let on_load = RefCell::new(Some(on_load));
+        let deliver = Rc::new(move |position: usize, upload: Upload| {
+            loaded.borrow_mut().push((position, upload));
+            if loaded.borrow().len() == count
+                && let Some(on_load) = on_load.borrow_mut().take()
+            {
+                let mut uploads = loaded.take();
+                uploads.sort_unstable_by_key(|(position, _)| *position);
+                on_load(uploads.into_iter().map(|(_, upload)| upload).collect());
+            }
+        });
+
+        for (position, file) in (0..files.length()).filter_map(|i| files.get(i)).enumerate() {
+            let name = file.name();
+            let Ok(reader) = FileReader::new() else {
+                deliver(position, Upload { name, bytes: None });
+                continue;
+            };
+
+            let on_load_end = {
+                let deliver = Rc::clone(&deliver);
+                let name = name.clone();
+                let reader = reader.clone();
+                Closure::once(move || {
+                    let bytes = reader
+                        .result()
+                        .ok()
+                        .filter(|result| result.is_instance_of::<ArrayBuffer>())
+                        .map(|result| Uint8Array::new(&result).to_vec());
+                    deliver(position, Upload { name, bytes });
+                })
+            };
+            reader.set_onloadend(Some(on_load_end.as_ref().unchecked_ref()));
+            on_load_end.forget();
+            // a read that fails to start never ends either
+            if reader.read_as_array_buffer(&file).is_err() {
+                deliver(position, Upload { name, bytes: None });
+            }
+        }
+    }
+
+    /// Opens the browser's file picker, calling `on_pick` with the chosen files before they are
+    /// read. Nothing happens if the visitor cancels.
+    pub fn pick_files(multiple: bool, on_pick: impl FnOnce(FileList) + 'static) {
+        let Some(input) = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.create_element("input").ok())
+            .and_then(|element| element.dyn_into::<HtmlInputElement>().ok())
+        else {
+            return;
+        };
+        input.set_type("file");
+        input.set_multiple(multiple);
+
+        let picked = input.clone();
+        let on_change = Closure::once(move || {
+            if let Some(files) = picked.files()
+                && files.length() > 0
+            {
+                on_pick(files);
+            }
+        });
+        input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+        on_change.forget();
+        input.click();
+    }
+}
+
+/// There is no browser to hand files to or take them from off the web.
+#[cfg(not(target_arch = "wasm32"))]
+mod browser {
+    use web_sys::FileList;
+
+    use super::{TransferError, Upload};
+
+    pub fn download(_name: &str, _bytes: &[u8], _mime: &str) -> Result<(), TransferError> {
+        Err(TransferError::Unsupported)
+    }
+
+    pub fn read_files(_files: &FileList, _on_load: impl FnOnce(Vec<Upload>) + 'static) {}
+
+    pub fn pick_files(_multiple: bool, _on_pick: impl FnOnce(FileList) + 'static) {}
+}
+
+pub use browser::{download, pick_files, read_files};